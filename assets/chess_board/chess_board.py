@@ -5,7 +5,14 @@ import taichi as ti
 WIDTH = 16
 HEIGHT = 16
 
-def compile_graph_aot(arch):
+ARCHS = {
+    "vulkan": ti.vulkan,
+    "metal": ti.metal,
+    "x64": ti.x64,
+}
+
+def compile_graph_aot(arch_name):
+    arch = ARCHS[arch_name]
     ti.init(arch=arch)
 
     if ti.lang.impl.current_cfg().arch != arch:
@@ -30,17 +37,13 @@ def compile_graph_aot(arch):
 
     mod = ti.aot.Module(arch)
     mod.add_graph('g_run', run_graph)
+    mod.add_kernel(chess_board)
     mod.save("assets/chess_board/module")
 
 
 if __name__ == "__main__":
     parser = argparse.ArgumentParser()
-    parser.add_argument("--arch", type=str)
+    parser.add_argument("--arch", type=str, choices=ARCHS.keys(), required=True)
     args = parser.parse_args()
 
-    if args.arch == "vulkan":
-        compile_graph_aot(arch=ti.vulkan)
-    elif args.arch == "metal":
-        compile_graph_aot(arch=ti.metal)
-    else:
-        assert False
+    compile_graph_aot(args.arch)
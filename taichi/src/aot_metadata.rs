@@ -0,0 +1,176 @@
+use std::collections::BTreeMap;
+use std::path::Path;
+use serde_json::Value;
+use taichi_sys::TiDataType;
+
+use crate::{BindingError, Result};
+
+// Mirrors `ti.graph.ArgKind`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ArgKind {
+    Scalar,
+    Ndarray,
+    Matrix,
+    Texture,
+    RwTexture,
+}
+impl ArgKind {
+    // Taichi serializes the enum by its underlying value.
+    fn from_json(value: &Value) -> Option<Self> {
+        let kind = match value.as_u64()? {
+            0 => ArgKind::Scalar,
+            1 => ArgKind::Ndarray,
+            2 => ArgKind::Matrix,
+            3 => ArgKind::Texture,
+            4 => ArgKind::RwTexture,
+            _ => return None,
+        };
+        Some(kind)
+    }
+}
+
+fn data_type_from_json(value: &Value) -> Option<TiDataType> {
+    // Taichi's `PrimitiveTypeID` shares its ordering with `TiDataType`.
+    const DATA_TYPES: [TiDataType; 14] = [
+        TiDataType::F16,
+        TiDataType::F32,
+        TiDataType::F64,
+        TiDataType::I8,
+        TiDataType::I16,
+        TiDataType::I32,
+        TiDataType::I64,
+        TiDataType::U1,
+        TiDataType::U8,
+        TiDataType::U16,
+        TiDataType::U32,
+        TiDataType::U64,
+        TiDataType::Gen,
+        TiDataType::Unknown,
+    ];
+    DATA_TYPES.get(value.as_u64()? as usize).copied()
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ArgDescriptor {
+    pub name: String,
+    pub kind: ArgKind,
+    pub dtype: TiDataType,
+    pub ndim: u32,
+    pub elem_shape: Vec<u32>,
+}
+impl ArgDescriptor {
+    fn from_json(name: &str, value: &Value) -> Result<Self> {
+        let field = |field: &str| value.get(field)
+            .ok_or_else(|| invalid(format!("graph argument {:?} has no {:?}", name, field)));
+        let kind = ArgKind::from_json(field("tag")?)
+            .ok_or_else(|| invalid(format!("graph argument {:?} has an invalid kind", name)))?;
+        let dtype = data_type_from_json(field("dtype_id")?)
+            .ok_or_else(|| invalid(format!("graph argument {:?} has an invalid data type", name)))?;
+        let ndim = field("field_dim")?.as_u64()
+            .and_then(|x| u32::try_from(x).ok())
+            .ok_or_else(|| invalid(format!("graph argument {:?} has an invalid field dimension", name)))?;
+        let elem_shape = field("element_shape")?.as_array()
+            .and_then(|x| x.iter()
+                .map(|x| x.as_u64().and_then(|x| u32::try_from(x).ok()))
+                .collect::<Option<Vec<_>>>())
+            .ok_or_else(|| invalid(format!("graph argument {:?} has an invalid element shape", name)))?;
+        Ok(ArgDescriptor { name: name.to_owned(), kind, dtype, ndim, elem_shape })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GraphDescriptor {
+    pub name: String,
+    pub args: Vec<ArgDescriptor>,
+}
+impl GraphDescriptor {
+    pub fn arg(&self, name: &str) -> Option<&ArgDescriptor> {
+        self.args.iter().find(|x| x.name == name)
+    }
+}
+
+fn invalid<S: ToString>(message: S) -> BindingError {
+    BindingError::InvalidMetadata(message.to_string())
+}
+fn parse(what: &str, json: &str) -> Result<Value> {
+    serde_json::from_str(json)
+        .map_err(|e| invalid(format!("{} is not valid json: {}", what, e)).into())
+}
+// Taichi's json serializer writes maps as lists of `{ "key": .., "value": .. }`
+// pairs.
+fn map_entries<'a>(what: &str, value: &'a Value) -> Result<Vec<(&'a str, &'a Value)>> {
+    let entries = value.as_array()
+        .ok_or_else(|| invalid(format!("{} is not a list of key-value pairs", what)))?;
+    entries.iter()
+        .map(|x| match (x.get("key").and_then(Value::as_str), x.get("value")) {
+            (Some(key), Some(value)) => Ok((key, value)),
+            _ => Err(invalid(format!("{} has an entry without a string key and a value", what)).into()),
+        })
+        .collect()
+}
+
+// Kernels and graphs exported by an AOT module, parsed from the
+// `metadata.json` and `graphs.json` that `ti.aot.Module.save` writes.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct AotMetadata {
+    kernel_names: Vec<String>,
+    graphs: BTreeMap<String, GraphDescriptor>,
+}
+impl AotMetadata {
+    pub const METADATA_FILE_NAME: &'static str = "metadata.json";
+    pub const GRAPHS_FILE_NAME: &'static str = "graphs.json";
+
+    pub fn from_json(metadata: Option<&str>, graphs: Option<&str>) -> Result<Self> {
+        let mut out = AotMetadata::default();
+        if let Some(metadata) = metadata {
+            let metadata = parse(Self::METADATA_FILE_NAME, metadata)?;
+            let kernels = metadata.get("kernels").and_then(Value::as_array)
+                .ok_or_else(|| invalid(format!("{} has no kernel list", Self::METADATA_FILE_NAME)))?;
+            for kernel in kernels {
+                let name = kernel.get("name").and_then(Value::as_str)
+                    .ok_or_else(|| invalid("kernel has no name"))?;
+                out.kernel_names.push(name.to_owned());
+            }
+        }
+        if let Some(graphs) = graphs {
+            let graphs = parse(Self::GRAPHS_FILE_NAME, graphs)?;
+            for (name, graph) in map_entries(Self::GRAPHS_FILE_NAME, &graphs)? {
+                let args = graph.get("args")
+                    .ok_or_else(|| invalid(format!("graph {:?} has no arguments", name)))?;
+                let mut args = map_entries(&format!("arguments of graph {:?}", name), args)?.into_iter()
+                    .map(|(name, arg)| ArgDescriptor::from_json(name, arg))
+                    .collect::<Result<Vec<_>>>()?;
+                args.sort_by(|a, b| a.name.cmp(&b.name));
+                out.graphs.insert(name.to_owned(), GraphDescriptor { name: name.to_owned(), args });
+            }
+        }
+        Ok(out)
+    }
+    // Returns `None` if the directory has neither metadata file, e.g. when
+    // it was saved by a Taichi version with a different layout.
+    pub fn load(module_dir: &Path) -> Result<Option<Self>> {
+        let read = |file_name: &str| -> Result<Option<String>> {
+            match std::fs::read_to_string(module_dir.join(file_name)) {
+                Ok(x) => Ok(Some(x)),
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+                Err(e) => Err(invalid(format!("cannot read {}: {}", file_name, e)).into()),
+            }
+        };
+        let metadata = read(Self::METADATA_FILE_NAME)?;
+        let graphs = read(Self::GRAPHS_FILE_NAME)?;
+        if metadata.is_none() && graphs.is_none() {
+            return Ok(None);
+        }
+        Self::from_json(metadata.as_deref(), graphs.as_deref()).map(Some)
+    }
+
+    pub fn kernel_names(&self) -> Vec<&str> {
+        self.kernel_names.iter().map(String::as_str).collect()
+    }
+    pub fn graph_names(&self) -> Vec<&str> {
+        self.graphs.keys().map(String::as_str).collect()
+    }
+    pub fn graph(&self, name: &str) -> Option<&GraphDescriptor> {
+        self.graphs.get(name)
+    }
+}
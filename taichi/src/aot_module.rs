@@ -1,74 +1,156 @@
-use std::{rc::Rc, ffi::c_void};
+use std::ffi::c_void;
+use crate::sync::Shared;
 use std::ffi::CString;
+use std::io::Read;
+use std::path::Path;
 use taichi_sys::*;
 use crate::{
-    get_last_error, Error, Result,
+    get_last_error, BindingError, Result,
     runtime::Runtime,
+    kernel::Kernel,
     compute_graph::ComputeGraph,
+    aot_metadata::{AotMetadata, ArgDescriptor, GraphDescriptor},
+    tcm,
 };
 
+// The C-API takes a narrow string path. Unix paths are passed through as raw
+// bytes so non-UTF-8 paths still work; elsewhere they must be valid UTF-8.
+pub(crate) fn path_to_cstring(path: &Path) -> Result<CString> {
+    #[cfg(unix)]
+    let bytes = {
+        use std::os::unix::ffi::OsStrExt;
+        path.as_os_str().as_bytes()
+    };
+    #[cfg(not(unix))]
+    let bytes = path.to_str()
+        .ok_or_else(|| BindingError::InvalidPath(path.display().to_string()))?
+        .as_bytes();
+    let out = CString::new(bytes)
+        .map_err(|_| BindingError::NulInName(path.display().to_string()))?;
+    Ok(out)
+}
+
 struct AotModule_ {
     runtime: Runtime,
     aot_module: TiAotModule,
+    // Metadata that fails to parse doesn't prevent the module from loading;
+    // the error is kept and returned by everything that needs the metadata.
+    metadata: Result<Option<AotMetadata>>,
 }
 impl AotModule_ {
-    pub fn load(runtime: &Runtime, module_dir: &str) -> Result<AotModule_> {
-        let module_dir = CString::new(module_dir)
-            .map_err(|_| Error::InvalidArgument(module_dir))?;
+    pub fn load(runtime: &Runtime, module_dir: &Path) -> Result<AotModule_> {
+        let module_dir_c = path_to_cstring(module_dir)?;
+        let module_dir_name = module_dir.display().to_string();
+        let metadata = AotMetadata::load(module_dir)
+            .map_err(|e| e.with_context("read aot module metadata", Some(&module_dir_name)));
+        let _guard = runtime.enter()?;
         let aot_module = unsafe {
-            ti_load_aot_module(runtime.runtime(), module_dir.as_ptr())
+            ti_load_aot_module(runtime.runtime(), module_dir_c.as_ptr())
         };
-        get_last_error()?;
+        get_last_error()
+            .map_err(|e| e.with_context("load aot module", Some(&module_dir_name)))?;
         let out = AotModule_ {
             runtime: runtime.clone(),
             aot_module,
+            metadata,
         };
         Ok(out)
     }
     pub fn new(runtime: &Runtime, tcm: &[u8]) -> Result<AotModule_> {
+        // Leave archives that can't be read here to the native loader.
+        let metadata = match tcm::open_archive(tcm) {
+            Ok(mut x) => tcm::read_metadata(&mut x)
+                .map_err(|e| e.with_context("read aot module metadata", None)),
+            Err(_) => Ok(None),
+        };
+        let _guard = runtime.enter()?;
         let aot_module = unsafe {
             ti_create_aot_module(runtime.runtime(), tcm.as_ptr() as *const c_void, tcm.len() as u64)
         };
-        get_last_error()?;
+        get_last_error()
+            .map_err(|e| e.with_context("create aot module", None))?;
         let out = AotModule_ {
             runtime: runtime.clone(),
             aot_module,
+            metadata,
         };
         Ok(out)
     }
 }
 impl Drop for AotModule_ {
     fn drop(&mut self) {
-        unsafe {
-            ti_destroy_aot_module(self.aot_module);
-        }
+        let aot_module = self.aot_module;
+        self.runtime.release(move |_| unsafe {
+            ti_destroy_aot_module(aot_module);
+        });
     }
 }
 
 #[derive(Clone)]
 pub struct AotModule {
-    inner: Rc<AotModule_>,
+    inner: Shared<AotModule_>,
 }
 impl AotModule {
-    pub fn load(runtime: &Runtime, module_dir: &str) -> Result<AotModule> {
-        let inner = AotModule_::load(runtime, module_dir)?;
+    pub fn load<P: AsRef<Path>>(runtime: &Runtime, module_dir: P) -> Result<AotModule> {
+        let inner = AotModule_::load(runtime, module_dir.as_ref())?;
         let out = AotModule {
-            inner: Rc::new(inner),
+            inner: Shared::new(inner),
         };
         Ok(out)
     }
     pub fn new(runtime: &Runtime, tcm: &[u8]) -> Result<AotModule> {
         let inner = AotModule_::new(runtime, tcm)?;
         let out = AotModule {
-            inner: Rc::new(inner),
+            inner: Shared::new(inner),
         };
         Ok(out)
     }
+    pub fn from_reader<R: Read>(runtime: &Runtime, mut reader: R) -> Result<AotModule> {
+        let mut tcm = Vec::new();
+        reader.read_to_end(&mut tcm)
+            .map_err(BindingError::from)?;
+        AotModule::new(runtime, &tcm)
+    }
 
+    pub fn get_kernel(&self, name: &str) -> Result<Kernel> {
+        Kernel::new(self, name)
+    }
     pub fn get_compute_graph(&self, name: &str) -> Result<ComputeGraph> {
         ComputeGraph::new(self, name)
     }
+    pub fn get_compute_graph_unchecked(&self, name: &str) -> Result<ComputeGraph> {
+        ComputeGraph::new_unchecked(self, name)
+    }
+
+    pub fn metadata(&self) -> Result<Option<&AotMetadata>> {
+        match &self.inner.metadata {
+            Ok(x) => Ok(x.as_ref()),
+            Err(e) => Err(e.clone()),
+        }
+    }
+    fn require_metadata(&self) -> Result<&AotMetadata> {
+        self.metadata()?
+            .ok_or_else(|| BindingError::MetadataUnavailable.into())
+    }
+    pub fn kernel_names(&self) -> Result<Vec<&str>> {
+        Ok(self.require_metadata()?.kernel_names())
+    }
+    pub fn graph_names(&self) -> Result<Vec<&str>> {
+        Ok(self.require_metadata()?.graph_names())
+    }
+    pub fn graph_args(&self, name: &str) -> Result<&[ArgDescriptor]> {
+        Ok(&self.graph(name)?.args)
+    }
+    pub(crate) fn graph(&self, name: &str) -> Result<&GraphDescriptor> {
+        let graph = self.require_metadata()?
+            .graph(name)
+            .ok_or_else(|| BindingError::UnknownName { kind: "compute graph", name: name.to_owned() })?;
+        Ok(graph)
+    }
 
+    pub(crate) fn parent_runtime(&self) -> &Runtime {
+        &self.inner.runtime
+    }
     pub fn runtime(&self) -> TiRuntime {
         self.inner.runtime.runtime()
     }
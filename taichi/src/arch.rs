@@ -0,0 +1,18 @@
+use taichi_sys::{TiArch, ti_get_available_archs};
+
+use crate::{get_last_error, Result};
+
+pub fn available_archs() -> Result<Vec<TiArch>> {
+    let mut arch_count: u32 = 0;
+    unsafe {
+        ti_get_available_archs(&mut arch_count as *mut u32, std::ptr::null_mut());
+    }
+    get_last_error()?;
+    let mut archs = vec![TiArch::Reserved; arch_count as usize];
+    unsafe {
+        ti_get_available_archs(&mut arch_count as *mut u32, archs.as_mut_ptr());
+    }
+    get_last_error()?;
+    archs.truncate(arch_count as usize);
+    Ok(archs)
+}
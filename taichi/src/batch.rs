@@ -0,0 +1,99 @@
+use crate::{
+    BindingError, Result,
+    runtime::Runtime,
+    memory::{Memory, MemorySlice},
+    image::{Image, ImageRegion},
+    kernel::Kernel,
+    compute_graph::ComputeGraph,
+};
+
+enum Command {
+    LaunchComputeGraph(ComputeGraph),
+    LaunchKernel(Kernel),
+    CopyMemory { dst: (Memory, usize, usize), src: (Memory, usize, usize) },
+    CopyImage { dst: Image, dst_region: ImageRegion, src: Image, src_region: ImageRegion },
+}
+impl Command {
+    fn enqueue(&self) -> Result<()> {
+        match self {
+            Command::LaunchComputeGraph(compute_graph) => compute_graph.launch().map(drop),
+            Command::LaunchKernel(kernel) => kernel.launch().map(drop),
+            Command::CopyMemory { dst, src } => {
+                let dst_slice = MemorySlice::new_unchecked(&dst.0, dst.1, dst.2);
+                dst_slice.copy_from(&MemorySlice::new_unchecked(&src.0, src.1, src.2))
+            },
+            Command::CopyImage { dst, dst_region, src, src_region } => {
+                dst.copy_from(dst_region, src, src_region)
+            },
+        }
+    }
+}
+
+// Launches and copies are recorded with the arguments bound at the time and
+// keep the resources they use alive. They are enqueued in order and flushed
+// together when the batch ends, or dropped without being submitted if the
+// batch closure fails.
+pub struct Batch<'a> {
+    runtime: &'a Runtime,
+    commands: Vec<Command>,
+}
+impl<'a> Batch<'a> {
+    pub(crate) fn new(runtime: &'a Runtime) -> Self {
+        Batch {
+            runtime,
+            commands: Vec::new(),
+        }
+    }
+
+    fn check_runtime(&self, runtime: taichi_sys::TiRuntime) -> Result<()> {
+        if runtime != self.runtime.runtime() {
+            return Err(BindingError::RuntimeMismatch.into());
+        }
+        Ok(())
+    }
+
+    pub fn launch_compute_graph(&mut self, compute_graph: &ComputeGraph) -> Result<&mut Self> {
+        self.check_runtime(compute_graph.runtime())?;
+        self.commands.push(Command::LaunchComputeGraph(compute_graph.clone()));
+        Ok(self)
+    }
+    pub fn launch_kernel(&mut self, kernel: &Kernel) -> Result<&mut Self> {
+        self.check_runtime(kernel.runtime())?;
+        self.commands.push(Command::LaunchKernel(kernel.clone()));
+        Ok(self)
+    }
+    pub fn copy_memory(&mut self, dst: &MemorySlice<'_>, src: &MemorySlice<'_>) -> Result<&mut Self> {
+        self.check_runtime(dst.memory().runtime())?;
+        dst.check_copy_from(src)?;
+        self.commands.push(Command::CopyMemory {
+            dst: (dst.memory().clone(), dst.offset(), dst.size()),
+            src: (src.memory().clone(), src.offset(), src.size()),
+        });
+        Ok(self)
+    }
+    pub fn copy_image(&mut self, dst: &Image, dst_region: &ImageRegion, src: &Image, src_region: &ImageRegion) -> Result<&mut Self> {
+        self.check_runtime(dst.runtime())?;
+        dst.check_copy_from(dst_region, src, src_region)?;
+        self.commands.push(Command::CopyImage {
+            dst: dst.clone(),
+            dst_region: *dst_region,
+            src: src.clone(),
+            src_region: *src_region,
+        });
+        Ok(self)
+    }
+
+    // Commands enqueued before a failing one are still flushed.
+    pub(crate) fn submit(self) -> Result<()> {
+        let enqueued = self.commands.iter().try_for_each(Command::enqueue);
+        let flushed = self.runtime.flush();
+        enqueued.and(flushed)
+    }
+
+    pub fn runtime(&self) -> &Runtime {
+        self.runtime
+    }
+    pub fn command_count(&self) -> usize {
+        self.commands.len()
+    }
+}
@@ -1,28 +1,95 @@
-use std::rc::Rc;
+use crate::sync::Shared;
 use std::ffi::CString;
 use std::collections::HashMap;
 use taichi_sys::*;
 use crate::{
-    get_last_error, Error, Result,
+    get_last_error, BindingError, Result,
     aot_module::AotModule,
+    submission::Submission,
+    scalar::Scalar,
+    element::Element,
     ndarray::NdArray,
+    texture::Texture,
+    aot_metadata::{ArgDescriptor, ArgKind, GraphDescriptor},
 };
 
+fn mismatch(desc: &ArgDescriptor, what: &'static str, expected: impl std::fmt::Debug, actual: impl std::fmt::Debug) -> BindingError {
+    BindingError::ArgumentMismatch {
+        name: desc.name.clone(),
+        what,
+        expected: format!("{:?}", expected),
+        actual: format!("{:?}", actual),
+    }
+}
+pub(crate) fn check_arg(desc: &ArgDescriptor, arg: &TiArgument) -> Result<()> {
+    // The C-API has no argument type for matrices, so a matrix argument can
+    // only be bound to a scalar, which fills a matrix of a single element.
+    let kind_matches = matches!(
+        (desc.kind, arg.r#type),
+        (ArgKind::Scalar | ArgKind::Matrix, TiArgumentType::I32 | TiArgumentType::F32 | TiArgumentType::Scalar) |
+        (ArgKind::Ndarray, TiArgumentType::Ndarray) |
+        (ArgKind::Texture | ArgKind::RwTexture, TiArgumentType::Texture)
+    );
+    if !kind_matches {
+        return Err(mismatch(desc, "kind", desc.kind, arg.r#type).into());
+    }
+    if desc.kind == ArgKind::Matrix && desc.elem_shape.iter().product::<u32>() != 1 {
+        return Err(mismatch(desc, "element shape", desc.elem_shape.as_slice(), &[] as &[u32]).into());
+    }
+
+    let dtype = match arg.r#type {
+        TiArgumentType::I32 => TiDataType::I32,
+        TiArgumentType::F32 => TiDataType::F32,
+        TiArgumentType::Scalar => unsafe { arg.value.scalar.r#type },
+        TiArgumentType::Ndarray => unsafe { arg.value.ndarray.elem_type },
+        TiArgumentType::Texture => return Ok(()),
+    };
+    if desc.dtype != TiDataType::Unknown && dtype != desc.dtype {
+        return Err(mismatch(desc, "data type", desc.dtype, dtype).into());
+    }
+
+    if arg.r#type == TiArgumentType::Ndarray {
+        let ndarray = unsafe { &arg.value.ndarray };
+        if ndarray.shape.dim_count != desc.ndim {
+            return Err(mismatch(desc, "ndim", desc.ndim, ndarray.shape.dim_count).into());
+        }
+        let elem_shape = &ndarray.elem_shape.dims[..ndarray.elem_shape.dim_count as usize];
+        if elem_shape != desc.elem_shape.as_slice() {
+            return Err(mismatch(desc, "element shape", desc.elem_shape.as_slice(), elem_shape).into());
+        }
+    }
+    Ok(())
+}
+pub(crate) fn check_args_set(signature: &GraphDescriptor, args: &HashMap<CString, TiArgument>) -> Result<()> {
+    for desc in signature.args.iter() {
+        if !args.keys().any(|x| x.as_bytes() == desc.name.as_bytes()) {
+            return Err(BindingError::ArgumentNotSet(format!("{:?}", desc.name)).into());
+        }
+    }
+    Ok(())
+}
+
 struct ComputeGraph_ {
     aot_module: AotModule,
     compute_graph: TiComputeGraph,
+    signature: Option<GraphDescriptor>,
+    name: String,
 }
 impl ComputeGraph_ {
-    pub fn new(aot_module: &AotModule, name: &str) -> Result<ComputeGraph_> {
-        let name = CString::new(name)
-            .map_err(|_| Error::InvalidArgument(name))?;
+    pub fn new(aot_module: &AotModule, name: &str, signature: Option<GraphDescriptor>) -> Result<ComputeGraph_> {
+        let name_c = CString::new(name)
+            .map_err(|_| BindingError::NulInName(name.to_owned()))?;
+        let _guard = aot_module.parent_runtime().enter()?;
         let compute_graph = unsafe {
-            ti_get_aot_module_compute_graph(aot_module.aot_module(), name.as_ptr())
+            ti_get_aot_module_compute_graph(aot_module.aot_module(), name_c.as_ptr())
         };
-        get_last_error()?;
+        get_last_error()
+            .map_err(|e| e.with_context("get compute graph", Some(name)))?;
         let out = ComputeGraph_ {
             aot_module: aot_module.clone(),
             compute_graph,
+            signature,
+            name: name.to_owned(),
         };
         Ok(out)
     }
@@ -30,56 +97,93 @@ impl ComputeGraph_ {
 
 #[derive(Clone)]
 pub struct ComputeGraph {
-    inner: Rc<ComputeGraph_>,
+    inner: Shared<ComputeGraph_>,
     args: HashMap<CString, TiArgument>,
 }
 impl ComputeGraph {
+    // Arguments are checked against the graph signature in the module
+    // metadata, so the graph can't be created if the metadata is missing or
+    // broken.
     pub fn new(aot_module: &AotModule, name: &str) -> Result<ComputeGraph> {
-        let inner = ComputeGraph_::new(aot_module, name)?;
+        let signature = aot_module.graph(name)
+            .map_err(|e| e.with_context("get compute graph", Some(name)))?
+            .clone();
+        Self::with_signature(aot_module, name, Some(signature))
+    }
+    // Arguments are passed through unchecked, e.g. for modules saved by a
+    // Taichi version whose metadata can't be parsed.
+    pub fn new_unchecked(aot_module: &AotModule, name: &str) -> Result<ComputeGraph> {
+        Self::with_signature(aot_module, name, None)
+    }
+    fn with_signature(aot_module: &AotModule, name: &str, signature: Option<GraphDescriptor>) -> Result<ComputeGraph> {
+        let inner = ComputeGraph_::new(aot_module, name, signature)?;
         let out = ComputeGraph {
-            inner: Rc::new(inner),
+            inner: Shared::new(inner),
             args: Default::default()
         };
         Ok(out)
     }
 
-    pub fn set_arg_i32(&mut self, name: &str, value: i32) -> Result<&mut Self> {
+    fn set_arg(&mut self, name: &str, arg: TiArgument) -> Result<&mut Self> {
+        if let Some(signature) = self.signature() {
+            let desc = signature.arg(name)
+                .ok_or_else(|| BindingError::UnknownArgument(name.to_owned()))?;
+            check_arg(desc, &arg)?;
+        }
         let name = CString::new(name)
-            .map_err(|_| Error::InvalidArgument(name))?;
+            .map_err(|_| BindingError::NulInName(name.to_owned()))?;
+        self.args.insert(name, arg);
+        Ok(self)
+    }
+    pub fn set_arg_i32(&mut self, name: &str, value: i32) -> Result<&mut Self> {
         let arg = TiArgument {
             r#type: TiArgumentType::I32,
             value: TiArgumentValue {
                 r#i32: value,
             },
         };
-        self.args.insert(name, arg);
-        Ok(self)
+        self.set_arg(name, arg)
     }
     pub fn set_arg_f32(&mut self, name: &str, value: f32) -> Result<&mut Self> {
-        let name = CString::new(name)
-            .map_err(|_| Error::InvalidArgument(name))?;
         let arg = TiArgument {
             r#type: TiArgumentType::F32,
             value: TiArgumentValue {
                 r#f32: value,
             },
         };
-        self.args.insert(name, arg);
-        Ok(self)
+        self.set_arg(name, arg)
     }
-    pub fn set_arg_ndarray<T>(&mut self, name: &str, value: &NdArray<T>) -> Result<&mut Self> {
-        let name = CString::new(name)
-            .map_err(|_| Error::InvalidArgument(name))?;
+    pub fn set_arg_scalar<T: Scalar>(&mut self, name: &str, value: T) -> Result<&mut Self> {
+        let arg = TiArgument {
+            r#type: TiArgumentType::Scalar,
+            value: TiArgumentValue {
+                scalar: value.to_scalar(),
+            },
+        };
+        self.set_arg(name, arg)
+    }
+    pub fn set_arg_ndarray<T: Element>(&mut self, name: &str, value: &NdArray<T>) -> Result<&mut Self> {
         let arg = TiArgument {
             r#type: TiArgumentType::Ndarray,
             value: TiArgumentValue {
-                ndarray: value.ndarray().clone(),
+                ndarray: *value.ndarray(),
             },
         };
-        self.args.insert(name, arg);
-        Ok(self)
+        self.set_arg(name, arg)
+    }
+    pub fn set_arg_texture(&mut self, name: &str, value: &Texture) -> Result<&mut Self> {
+        let arg = TiArgument {
+            r#type: TiArgumentType::Texture,
+            value: TiArgumentValue {
+                texture: *value.texture(),
+            },
+        };
+        self.set_arg(name, arg)
     }
 
+    pub fn signature(&self) -> Option<&GraphDescriptor> {
+        self.inner.signature.as_ref()
+    }
     pub fn runtime(&self) -> TiRuntime {
         self.inner.aot_module.runtime()
     }
@@ -87,24 +191,30 @@ impl ComputeGraph {
         self.inner.compute_graph
     }
 
-    pub fn launch(&self) -> Result<()> {
+    pub fn launch(&self) -> Result<Submission> {
+        if let Some(signature) = self.signature() {
+            check_args_set(signature, &self.args)?;
+        }
+
         let mut args = Vec::with_capacity(self.args.len());
 
         for (name, argument) in self.args.iter() {
             let arg = TiNamedArgument {
                 name: name.as_ptr(),
-                argument: argument.clone(),
+                argument: *argument,
             };
             args.push(arg);
         }
 
         let runtime = self.runtime();
         let compute_graph = self.compute_graph();
+        let _guard = self.inner.aot_module.parent_runtime().enter()?;
         unsafe {
             ti_launch_compute_graph(runtime, compute_graph, args.len() as u32, args.as_ptr());
         }
-        get_last_error()?;
+        get_last_error()
+            .map_err(|e| e.with_context("launch compute graph", Some(&self.inner.name)))?;
 
-        Ok(())
+        Ok(Submission::new(self.inner.aot_module.parent_runtime()))
     }
 }
@@ -0,0 +1,107 @@
+use bytemuck::Pod;
+use taichi_sys::TiDataType;
+
+use crate::scalar::{Sealed, Scalar};
+
+// Elements are read and written as raw bytes on the host, so they must be
+// plain old data.
+pub trait Element: Sealed + Pod {
+    const ELEM_TYPE: TiDataType;
+
+    fn elem_shape() -> Vec<u32> {
+        Vec::new()
+    }
+}
+
+impl<T: Scalar> Element for T {
+    const ELEM_TYPE: TiDataType = T::DATA_TYPE;
+}
+
+// A `u1` element stored in a byte. `bool` can't be used directly because
+// any byte other than 0 or 1 read back from the device would be invalid.
+#[cfg(feature = "u1")]
+#[repr(transparent)]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub struct U1(pub u8);
+#[cfg(feature = "u1")]
+impl From<bool> for U1 {
+    fn from(x: bool) -> Self {
+        U1(x as u8)
+    }
+}
+#[cfg(feature = "u1")]
+impl From<U1> for bool {
+    fn from(x: U1) -> Self {
+        x.0 != 0
+    }
+}
+#[cfg(feature = "u1")]
+unsafe impl bytemuck::Zeroable for U1 {}
+#[cfg(feature = "u1")]
+unsafe impl Pod for U1 {}
+#[cfg(feature = "u1")]
+impl Sealed for U1 {}
+#[cfg(feature = "u1")]
+impl Element for U1 {
+    const ELEM_TYPE: TiDataType = TiDataType::U1;
+}
+
+// Vectors are `[T; N]` and row-major matrices are `[[T; M]; N]`, matching the
+// element layout of `ti.types.vector` and `ti.types.matrix` ND-arrays.
+impl<T: Element, const N: usize> Sealed for [T; N] {}
+impl<T: Element, const N: usize> Element for [T; N] {
+    const ELEM_TYPE: TiDataType = T::ELEM_TYPE;
+
+    fn elem_shape() -> Vec<u32> {
+        let mut out = vec![N as u32];
+        out.extend(T::elem_shape());
+        out
+    }
+}
+
+// Only vector types are mapped for `glam` and `nalgebra`. Their matrices are
+// column-major and would be silently transposed in Taichi kernels.
+#[cfg(feature = "glam")]
+mod glam_impls {
+    use super::*;
+
+    macro_rules! impl_vector_element {
+        ($ty:ty, $scalar:ty, $n:literal) => {
+            impl Sealed for $ty {}
+            impl Element for $ty {
+                const ELEM_TYPE: TiDataType = <$scalar as Element>::ELEM_TYPE;
+
+                fn elem_shape() -> Vec<u32> {
+                    vec![$n]
+                }
+            }
+        };
+    }
+
+    impl_vector_element!(glam::Vec2, f32, 2);
+    impl_vector_element!(glam::Vec3, f32, 3);
+    impl_vector_element!(glam::Vec4, f32, 4);
+    impl_vector_element!(glam::DVec2, f64, 2);
+    impl_vector_element!(glam::DVec3, f64, 3);
+    impl_vector_element!(glam::DVec4, f64, 4);
+    impl_vector_element!(glam::IVec2, i32, 2);
+    impl_vector_element!(glam::IVec3, i32, 3);
+    impl_vector_element!(glam::IVec4, i32, 4);
+    impl_vector_element!(glam::UVec2, u32, 2);
+    impl_vector_element!(glam::UVec3, u32, 3);
+    impl_vector_element!(glam::UVec4, u32, 4);
+}
+
+#[cfg(feature = "nalgebra")]
+mod nalgebra_impls {
+    use super::*;
+
+    impl<T: Scalar + nalgebra::Scalar, const N: usize> Sealed for nalgebra::SVector<T, N> {}
+    impl<T: Scalar + nalgebra::Scalar, const N: usize> Element for nalgebra::SVector<T, N> {
+        const ELEM_TYPE: TiDataType = T::DATA_TYPE;
+
+        fn elem_shape() -> Vec<u32> {
+            vec![N as u32]
+        }
+    }
+}
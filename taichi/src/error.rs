@@ -1,22 +1,192 @@
 use std::ffi::{c_char, CString};
+use std::sync::Arc;
 
-use taichi_sys::{TiError, ti_get_last_error, ti_set_last_error};
+use taichi_sys::{TiArch, TiError, ti_get_last_error, ti_set_last_error};
 
-#[derive(Debug, Clone, PartialEq, Eq)]
-pub struct TaichiError {
-    code: TiError,
-    message: String,
+// The error a binding error is caused by. It's shared so that binding errors
+// stay `Clone`, and copies of the same source compare equal.
+#[derive(Debug)]
+pub struct ErrorSource<E: ?Sized>(pub(crate) Arc<E>);
+impl<E> ErrorSource<E> {
+    pub fn new(error: E) -> Self {
+        ErrorSource(Arc::new(error))
+    }
+}
+impl<E: ?Sized> Clone for ErrorSource<E> {
+    fn clone(&self) -> Self {
+        ErrorSource(self.0.clone())
+    }
+}
+impl<E: ?Sized> PartialEq for ErrorSource<E> {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+    }
+}
+impl<E: ?Sized> Eq for ErrorSource<E> {}
+impl<E: ?Sized> std::ops::Deref for ErrorSource<E> {
+    type Target = E;
+    fn deref(&self) -> &E {
+        &self.0
+    }
+}
+impl<E: std::fmt::Display + ?Sized> std::fmt::Display for ErrorSource<E> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.0.fmt(f)
+    }
+}
+impl<E: std::error::Error + ?Sized> std::error::Error for ErrorSource<E> {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        self.0.source()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub enum BindingError {
+    #[error("{0:?} contains a nul character")]
+    NulInName(String),
+    #[error("path {0:?} is not valid utf-8")]
+    InvalidPath(String),
+    #[error("io error")]
+    Io(#[source] ErrorSource<std::io::Error>),
+    #[error("{what} size mismatched: expected {expected}, got {actual}")]
+    SizeMismatch { what: &'static str, expected: usize, actual: usize },
+    #[error("{what} {value} is not a multiple of {granularity}")]
+    Unaligned { what: &'static str, value: usize, granularity: usize },
+    #[error("{dim_count} dimensions exceed the maximum of 16")]
+    DimOverflow { dim_count: usize },
+    #[error("memory is not host-readable")]
+    NotHostReadable,
+    #[error("memory is not host-writable")]
+    NotHostWritable,
+    #[error("allocating {requested} bytes exceeds the memory budget {budget} with {used} bytes in use")]
+    BudgetExceeded { requested: usize, used: usize, budget: usize },
+    #[error("no module variant for {arch:?}; available archs are {available:?}")]
+    IncompatibleModule { arch: TiArch, available: Vec<TiArch> },
+    #[error("invalid tcm archive: {0}")]
+    InvalidArchive(String),
+    #[error("cannot read tcm archive")]
+    Archive(#[source] ErrorSource<dyn std::error::Error + Send + Sync>),
+    #[error("invalid aot module metadata: {0}")]
+    InvalidMetadata(String),
+    #[error("aot module has no metadata")]
+    MetadataUnavailable,
+    #[error("aot module has no {kind} named {name:?}")]
+    UnknownName { kind: &'static str, name: String },
+    #[error("memory is already mapped")]
+    AlreadyMapped,
+    #[error("{0}")]
+    OutOfRange(String),
+    #[error("memory ranges [{}, {}) and [{}, {}) of the same memory overlap", .dst.0, .dst.1, .src.0, .src.1)]
+    OverlappingCopy { dst: (usize, usize), src: (usize, usize) },
+    #[error("{what} mismatched: expected {expected}, got {actual}")]
+    Mismatch { what: &'static str, expected: String, actual: String },
+    #[error("resources are not from the same runtime")]
+    RuntimeMismatch,
+    #[error("graph has no argument named {0:?}")]
+    UnknownArgument(String),
+    #[error("argument {name:?} {what} mismatched: expected {expected}, got {actual}")]
+    ArgumentMismatch { name: String, what: &'static str, expected: String, actual: String },
+    #[error("argument {0} is not set")]
+    ArgumentNotSet(String),
+    #[error("{0}")]
+    WrongThread(String),
+    #[error("none of the preferred archs {preferred:?} is available; available archs are {available:?}")]
+    ArchNotAvailable { preferred: Vec<TiArch>, available: Vec<TiArch> },
+}
+impl From<std::io::Error> for BindingError {
+    fn from(error: std::io::Error) -> Self {
+        BindingError::Io(ErrorSource::new(error))
+    }
+}
+impl BindingError {
+    // The closest C-API error code, so binding errors can still be reported
+    // through `set_last_error`.
+    pub fn code(&self) -> TiError {
+        match self {
+            BindingError::NulInName(_) => TiError::InvalidArgument,
+            BindingError::InvalidPath(_) => TiError::InvalidArgument,
+            BindingError::Io(_) => TiError::InvalidState,
+            BindingError::SizeMismatch { .. } => TiError::ArgumentOutOfRange,
+            BindingError::Unaligned { .. } => TiError::ArgumentOutOfRange,
+            BindingError::DimOverflow { .. } => TiError::ArgumentOutOfRange,
+            BindingError::NotHostReadable => TiError::InvalidState,
+            BindingError::NotHostWritable => TiError::InvalidState,
+            BindingError::BudgetExceeded { .. } => TiError::OutOfMemory,
+            BindingError::IncompatibleModule { .. } => TiError::IncompatibleModule,
+            BindingError::InvalidArchive(_) => TiError::CorruptedData,
+            BindingError::Archive(_) => TiError::CorruptedData,
+            BindingError::InvalidMetadata(_) => TiError::CorruptedData,
+            BindingError::MetadataUnavailable => TiError::NotSupported,
+            BindingError::UnknownName { .. } => TiError::NameNotFound,
+            BindingError::AlreadyMapped => TiError::InvalidState,
+            BindingError::OutOfRange(_) => TiError::ArgumentOutOfRange,
+            BindingError::OverlappingCopy { .. } => TiError::InvalidArgument,
+            BindingError::Mismatch { .. } => TiError::InvalidArgument,
+            BindingError::RuntimeMismatch => TiError::InvalidArgument,
+            BindingError::UnknownArgument(_) => TiError::ArgumentNotFound,
+            BindingError::ArgumentMismatch { .. } => TiError::InvalidArgument,
+            BindingError::ArgumentNotSet(_) => TiError::ArgumentNotFound,
+            BindingError::WrongThread(_) => TiError::InvalidState,
+            BindingError::ArchNotAvailable { .. } => TiError::NotSupported,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub enum TaichiError {
+    #[error("{code:?} {message}")]
+    CApi { code: TiError, message: String },
+    #[error(transparent)]
+    Binding(#[from] BindingError),
+    #[error("failed to {operation}{}", .resource.as_ref().map(|x| format!(" {:?}", x)).unwrap_or_default())]
+    Context {
+        operation: &'static str,
+        resource: Option<String>,
+        #[source]
+        source: Box<TaichiError>,
+    },
 }
 impl TaichiError {
     pub fn new(code: TiError, message: String) -> Self {
-        Self { code, message }
+        Self::CApi { code, message }
+    }
+
+    pub fn with_context(self, operation: &'static str, resource: Option<&str>) -> Self {
+        Self::Context {
+            operation,
+            resource: resource.map(ToString::to_string),
+            source: Box::new(self),
+        }
     }
 
     pub fn code(&self) -> TiError {
-        self.code
+        match self {
+            Self::CApi { code, .. } => *code,
+            Self::Binding(e) => e.code(),
+            Self::Context { source, .. } => source.code(),
+        }
     }
-    pub fn message(&self) -> &str {
-        self.message.as_str()
+    // Binding errors and context are reported with their whole source chain,
+    // e.g. `failed to open "a.tcm": io error: No such file or directory`.
+    pub fn message(&self) -> String {
+        if let Self::CApi { message, .. } = self {
+            return message.clone();
+        }
+        let mut out = self.to_string();
+        let mut source = std::error::Error::source(self);
+        while let Some(e) = source {
+            out += ": ";
+            out += &e.to_string();
+            source = e.source();
+        }
+        out
+    }
+    pub fn binding_error(&self) -> Option<&BindingError> {
+        match self {
+            Self::CApi { .. } => None,
+            Self::Binding(e) => Some(e),
+            Self::Context { source, .. } => source.binding_error(),
+        }
     }
 
     #[allow(non_snake_case)]
@@ -68,16 +238,6 @@ impl TaichiError {
         Self::new(TiError::OutOfMemory, message.to_string())
     }
 }
-impl std::fmt::Display for TaichiError {
-    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
-        write!(f, "{:?} {}", self.code, self.message)
-    }
-}
-impl std::error::Error for TaichiError {
-    fn description(&self) -> &str {
-        self.message.as_str()
-    }
-}
 
 pub type TaichiResult<T> = std::result::Result<T, TaichiError>;
 
@@ -90,9 +250,8 @@ pub fn get_last_error() -> TaichiResult<()> {
         Ok(())
     } else {
         if message_size > 0 {
-            let mut message: Vec<u8> = Vec::with_capacity(message_size as usize);
+            let mut message: Vec<u8> = vec![0; message_size as usize];
             unsafe {
-                message.set_len(message_size as usize);
                 ti_get_last_error(&mut message_size as *mut u64, message.as_mut_ptr() as *mut c_char);
             }
             let message = String::from_utf8_lossy(&message).to_string();
@@ -104,8 +263,9 @@ pub fn get_last_error() -> TaichiResult<()> {
 }
 
 pub fn set_last_error(error: TaichiError) -> TaichiResult<()> {
-    let message = CString::new(error.message())
-        .map_err(|_| TaichiError::InvalidArgument("error message contains null character"))?;
+    let message = error.message();
+    let message = CString::new(message.as_str())
+        .map_err(|_| BindingError::NulInName(message.clone()))?;
     unsafe {
         ti_set_last_error(error.code(), message.as_ptr());
     }
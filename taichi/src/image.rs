@@ -1,7 +1,8 @@
-use std::rc::Rc;
-use taichi_sys::{TiImageAllocateInfo, TiImageDimension, TiImageExtent, TiFormat, TiImageUsageFlags, TI_TRUE, TiImage, ti_allocate_image, ti_free_image, TiRuntime, TI_FALSE};
+use crate::sync::Shared;
+use std::sync::Mutex;
+use taichi_sys::{TiImageAllocateInfo, TiImageDimension, TiImageExtent, TiFormat, TiImageUsageFlags, TI_TRUE, TiImage, ti_allocate_image, ti_free_image, TiRuntime, TI_FALSE, TiImageOffset, TiImageSlice, ti_copy_image_device_to_device, TiImageLayout, ti_transition_image, ti_track_image_ext};
 
-use crate::{get_last_error, Result, Runtime};
+use crate::{get_last_error, BindingError, Result, Runtime, scoped};
 
 pub struct ImageBuilder<'a> {
     runtime: &'a Runtime,
@@ -68,42 +69,66 @@ impl<'a> ImageBuilder<'a> {
     pub fn build(&self) -> Result<Image> {
         Image::new(self.runtime, &self.allocate_info)
     }
+    pub fn build_scoped(&self) -> Result<scoped::Image<'a>> {
+        scoped::Image::new(self.runtime, &self.allocate_info)
+    }
+}
+
+#[derive(Clone, Copy)]
+pub struct ImageRegion {
+    pub offset: TiImageOffset,
+    pub extent: TiImageExtent,
+    pub mip_level: u32,
+}
+impl ImageRegion {
+    pub fn new(offset: TiImageOffset, extent: TiImageExtent, mip_level: u32) -> Self {
+        ImageRegion { offset, extent, mip_level }
+    }
 }
 
 struct Image_ {
     runtime: Runtime,
     image: TiImage,
     allocate_info: TiImageAllocateInfo,
+    layout: Mutex<TiImageLayout>,
 }
 impl Image_ {
     pub fn new(runtime: &Runtime, allocate_info: &TiImageAllocateInfo) -> Result<Self> {
+        let _guard = runtime.enter()?;
+        runtime.memory_tracker().reserve_image(allocate_info)?;
         let image = unsafe {
             ti_allocate_image(runtime.runtime(), allocate_info)
         };
-        get_last_error()?;
+        if let Err(e) = get_last_error() {
+            runtime.memory_tracker().release_image(allocate_info);
+            return Err(e.with_context("allocate image", None));
+        }
         Ok(Image_ {
             runtime: runtime.clone(),
             image,
-            allocate_info: allocate_info.clone(),
+            allocate_info: *allocate_info,
+            layout: Mutex::new(TiImageLayout::Undefined),
         })
     }
 }
 impl Drop for Image_ {
     fn drop(&mut self) {
-        unsafe {
-            ti_free_image(self.runtime.runtime(), self.image);
-        }
+        let image = self.image;
+        self.runtime.release(move |runtime| unsafe {
+            ti_free_image(runtime, image);
+        });
+        self.runtime.memory_tracker().release_image(&self.allocate_info);
     }
 }
 
 #[derive(Clone)]
 pub struct Image {
-    inner: Rc<Image_>,
+    inner: Shared<Image_>,
 }
 impl Image {
     pub fn new(runtime: &Runtime, allocate_info: &TiImageAllocateInfo) -> Result<Self> {
         Ok(Image {
-            inner: Rc::new(Image_::new(runtime, allocate_info)?),
+            inner: Shared::new(Image_::new(runtime, allocate_info)?),
         })
     }
 
@@ -141,5 +166,121 @@ impl Image {
     pub fn usage(&self) -> TiImageUsageFlags {
         self.inner.allocate_info.usage
     }
-}
 
+    // The last layout the image is transitioned to or tracked as from Rust.
+    // Layout changes made implicitly by the runtime are not reflected.
+    pub fn layout(&self) -> TiImageLayout {
+        *self.inner.layout.lock().unwrap()
+    }
+    pub fn transition(&self, layout: TiImageLayout) -> Result<()> {
+        let _guard = self.inner.runtime.enter()?;
+        unsafe {
+            ti_transition_image(self.runtime(), self.image(), layout);
+        }
+        get_last_error()
+            .map_err(|e| e.with_context("transition image", None))?;
+        *self.inner.layout.lock().unwrap() = layout;
+        Ok(())
+    }
+    pub fn track_layout(&self, layout: TiImageLayout) -> Result<()> {
+        let _guard = self.inner.runtime.enter()?;
+        unsafe {
+            ti_track_image_ext(self.runtime(), self.image(), layout);
+        }
+        get_last_error()
+            .map_err(|e| e.with_context("track image layout", None))?;
+        *self.inner.layout.lock().unwrap() = layout;
+        Ok(())
+    }
+
+    pub fn mip_extent(&self, mip_level: u32) -> TiImageExtent {
+        let extent = &self.inner.allocate_info.extent;
+        TiImageExtent {
+            width: (extent.width >> mip_level).max(1),
+            height: (extent.height >> mip_level).max(1),
+            depth: (extent.depth >> mip_level).max(1),
+            array_layer_count: extent.array_layer_count,
+        }
+    }
+    pub fn region(&self, mip_level: u32) -> ImageRegion {
+        let offset = TiImageOffset { x: 0, y: 0, z: 0, array_layer_offset: 0 };
+        ImageRegion::new(offset, self.mip_extent(mip_level), mip_level)
+    }
+
+    fn validate_region(&self, region: &ImageRegion) -> Result<()> {
+        if region.mip_level >= self.mip_level_count() {
+            return Err(BindingError::OutOfRange(format!(
+                "mip level {} is out of the mip level count {}",
+                region.mip_level, self.mip_level_count())).into());
+        }
+        let mip_extent = self.mip_extent(region.mip_level);
+        let ranges = [
+            ("x", region.offset.x, region.extent.width, mip_extent.width),
+            ("y", region.offset.y, region.extent.height, mip_extent.height),
+            ("z", region.offset.z, region.extent.depth, mip_extent.depth),
+            ("array layer", region.offset.array_layer_offset, region.extent.array_layer_count, mip_extent.array_layer_count),
+        ];
+        for (axis, offset, extent, limit) in ranges {
+            if offset.checked_add(extent).is_none_or(|end| end > limit) {
+                return Err(BindingError::OutOfRange(format!(
+                    "image region {} range [{}, +{}) is out of the mip extent {}",
+                    axis, offset, extent, limit)).into());
+            }
+        }
+        Ok(())
+    }
+    fn image_slice(&self, region: &ImageRegion) -> TiImageSlice {
+        TiImageSlice {
+            image: self.image(),
+            offset: region.offset,
+            extent: region.extent,
+            mip_level: region.mip_level,
+        }
+    }
+
+    pub(crate) fn check_copy_from(&self, dst_region: &ImageRegion, src: &Image, src_region: &ImageRegion) -> Result<()> {
+        if self.runtime() != src.runtime() {
+            return Err(BindingError::RuntimeMismatch.into());
+        }
+        if self.format() != src.format() {
+            return Err(BindingError::Mismatch {
+                what: "image format",
+                expected: format!("{:?}", self.format()),
+                actual: format!("{:?}", src.format()),
+            }.into());
+        }
+        self.validate_region(dst_region)?;
+        src.validate_region(src_region)?;
+        let (dst_extent, src_extent) = (&dst_region.extent, &src_region.extent);
+        if dst_extent.width != src_extent.width ||
+            dst_extent.height != src_extent.height ||
+            dst_extent.depth != src_extent.depth ||
+            dst_extent.array_layer_count != src_extent.array_layer_count
+        {
+            let fmt_extent = |x: &TiImageExtent| format!(
+                "{}x{}x{} ({} layers)", x.width, x.height, x.depth, x.array_layer_count);
+            return Err(BindingError::Mismatch {
+                what: "image region extent",
+                expected: fmt_extent(dst_extent),
+                actual: fmt_extent(src_extent),
+            }.into());
+        }
+        Ok(())
+    }
+    // Images can only be copied from other images. Unlike `Memory::upload`
+    // and `Memory::download` there is no host transfer staged through a
+    // `Memory`, because the C-API has no memory-to-image copy and images
+    // can't be mapped. Fill images from kernels reading an ND-array instead.
+    pub fn copy_from(&self, dst_region: &ImageRegion, src: &Image, src_region: &ImageRegion) -> Result<()> {
+        self.check_copy_from(dst_region, src, src_region)?;
+        let dst = self.image_slice(dst_region);
+        let src = src.image_slice(src_region);
+        let _guard = self.inner.runtime.enter()?;
+        unsafe {
+            ti_copy_image_device_to_device(self.runtime(), &dst, &src);
+        }
+        get_last_error()
+            .map_err(|e| e.with_context("copy image", None))?;
+        Ok(())
+    }
+}
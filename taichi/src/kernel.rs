@@ -0,0 +1,138 @@
+use crate::sync::Shared;
+use std::ffi::CString;
+use taichi_sys::*;
+use crate::{
+    get_last_error, BindingError, Result,
+    aot_module::AotModule,
+    submission::Submission,
+    scalar::Scalar,
+    element::Element,
+    ndarray::NdArray,
+    texture::Texture,
+};
+
+struct Kernel_ {
+    aot_module: AotModule,
+    kernel: TiKernel,
+    name: String,
+}
+impl Kernel_ {
+    pub fn new(aot_module: &AotModule, name: &str) -> Result<Kernel_> {
+        let name_c = CString::new(name)
+            .map_err(|_| BindingError::NulInName(name.to_owned()))?;
+        let _guard = aot_module.parent_runtime().enter()?;
+        let kernel = unsafe {
+            ti_get_aot_module_kernel(aot_module.aot_module(), name_c.as_ptr())
+        };
+        get_last_error()
+            .map_err(|e| e.with_context("get kernel", Some(name)))?;
+        let out = Kernel_ {
+            aot_module: aot_module.clone(),
+            kernel,
+            name: name.to_owned(),
+        };
+        Ok(out)
+    }
+}
+
+#[derive(Clone)]
+pub struct Kernel {
+    inner: Shared<Kernel_>,
+    args: Vec<Option<TiArgument>>,
+}
+impl Kernel {
+    pub fn new(aot_module: &AotModule, name: &str) -> Result<Kernel> {
+        let inner = Kernel_::new(aot_module, name)?;
+        let out = Kernel {
+            inner: Shared::new(inner),
+            args: Default::default(),
+        };
+        Ok(out)
+    }
+
+    fn set_arg(&mut self, index: usize, arg: TiArgument) -> Result<&mut Self> {
+        if self.args.len() <= index {
+            self.args.resize(index + 1, None);
+        }
+        self.args[index] = Some(arg);
+        Ok(self)
+    }
+
+    pub fn set_arg_i32(&mut self, index: usize, value: i32) -> Result<&mut Self> {
+        let arg = TiArgument {
+            r#type: TiArgumentType::I32,
+            value: TiArgumentValue {
+                r#i32: value,
+            },
+        };
+        self.set_arg(index, arg)
+    }
+    pub fn set_arg_f32(&mut self, index: usize, value: f32) -> Result<&mut Self> {
+        let arg = TiArgument {
+            r#type: TiArgumentType::F32,
+            value: TiArgumentValue {
+                r#f32: value,
+            },
+        };
+        self.set_arg(index, arg)
+    }
+    pub fn set_arg_scalar<T: Scalar>(&mut self, index: usize, value: T) -> Result<&mut Self> {
+        let arg = TiArgument {
+            r#type: TiArgumentType::Scalar,
+            value: TiArgumentValue {
+                scalar: value.to_scalar(),
+            },
+        };
+        self.set_arg(index, arg)
+    }
+    pub fn set_arg_ndarray<T: Element>(&mut self, index: usize, value: &NdArray<T>) -> Result<&mut Self> {
+        let arg = TiArgument {
+            r#type: TiArgumentType::Ndarray,
+            value: TiArgumentValue {
+                ndarray: *value.ndarray(),
+            },
+        };
+        self.set_arg(index, arg)
+    }
+    pub fn set_arg_texture(&mut self, index: usize, value: &Texture) -> Result<&mut Self> {
+        let arg = TiArgument {
+            r#type: TiArgumentType::Texture,
+            value: TiArgumentValue {
+                texture: *value.texture(),
+            },
+        };
+        self.set_arg(index, arg)
+    }
+    pub fn clear_args(&mut self) -> &mut Self {
+        self.args.clear();
+        self
+    }
+
+    pub fn runtime(&self) -> TiRuntime {
+        self.inner.aot_module.runtime()
+    }
+    pub fn kernel(&self) -> TiKernel {
+        self.inner.kernel
+    }
+
+    pub fn launch(&self) -> Result<Submission> {
+        let mut args = Vec::with_capacity(self.args.len());
+
+        for (i, argument) in self.args.iter().enumerate() {
+            let arg = argument
+                .ok_or_else(|| BindingError::ArgumentNotSet(format!("#{}", i)))?;
+            args.push(arg);
+        }
+
+        let runtime = self.runtime();
+        let kernel = self.kernel();
+        let _guard = self.inner.aot_module.parent_runtime().enter()?;
+        unsafe {
+            ti_launch_kernel(runtime, kernel, args.len() as u32, args.as_ptr());
+        }
+        get_last_error()
+            .map_err(|e| e.with_context("launch kernel", Some(&self.inner.name)))?;
+
+        Ok(Submission::new(self.inner.aot_module.parent_runtime()))
+    }
+}
@@ -1,22 +1,58 @@
 #[cfg(test)]
 mod tests;
 
+mod sync;
 mod version;
+mod arch;
 mod error;
+mod scalar;
+mod element;
 mod runtime;
 mod memory;
+mod memory_pool;
+mod memory_report;
 mod ndarray;
 mod image;
+mod sampler;
 mod texture;
+mod aot_metadata;
 mod aot_module;
+mod module_bundle;
+mod kernel;
 mod compute_graph;
+mod batch;
+pub mod scoped;
+pub mod tcm;
+mod submission;
+
+// Embeds a TCM archive in the binary, to be loaded with
+// `Runtime::create_aot_module`. The path is relative to the invoking file.
+#[macro_export]
+macro_rules! include_tcm {
+    ($path:expr) => {
+        include_bytes!($path) as &'static [u8]
+    };
+}
 
 pub use version::{get_version, Version};
-pub use error::{get_last_error, set_last_error, TaichiError as Error, TaichiResult as Result};
+pub use arch::available_archs;
+pub use error::{get_last_error, set_last_error, BindingError, ErrorSource, TaichiError as Error, TaichiResult as Result};
+pub use scalar::Scalar;
+pub use element::Element;
+#[cfg(feature = "u1")]
+pub use element::U1;
 pub use runtime::Runtime;
-pub use memory::Memory;
+pub use memory::{Memory, MemorySlice, MappedMemory, MappedMemoryMut};
+pub use memory_pool::{MemoryPool, MemoryPoolStats, PoolAllocation};
+pub use memory_report::{AllocationStats, MemoryReport};
 pub use ndarray::NdArray;
-pub use image::Image;
+pub use image::{Image, ImageRegion};
+pub use sampler::Sampler;
 pub use texture::Texture;
+pub use aot_metadata::{AotMetadata, ArgDescriptor, ArgKind, GraphDescriptor};
 pub use aot_module::AotModule;
+pub use module_bundle::{ModuleBundle, ModuleSource};
+pub use kernel::Kernel;
 pub use compute_graph::ComputeGraph;
+pub use batch::Batch;
+pub use submission::Submission;
@@ -1,7 +1,13 @@
-use std::rc::Rc;
-use taichi_sys::{TiMemoryAllocateInfo, TI_FALSE, TiMemoryUsageFlags, TI_TRUE, TiMemory, ti_allocate_memory, ti_free_memory, TiRuntime, ti_map_memory, ti_unmap_memory};
+use crate::sync::Shared;
+use std::marker::PhantomData;
+use std::ops::{Deref, DerefMut};
+use std::sync::atomic::{AtomicBool, Ordering};
+use bytemuck::Pod;
+use taichi_sys::{TiMemoryAllocateInfo, TI_FALSE, TiMemoryUsageFlags, TI_TRUE, TiMemory, ti_allocate_memory, ti_free_memory, TiRuntime, ti_map_memory, ti_unmap_memory, TiMemorySlice, ti_copy_memory_device_to_device};
 
-use crate::{get_last_error, Error, Result, Runtime};
+use crate::{get_last_error, BindingError, Result, Runtime, scoped};
+
+const STAGING_SIZE: usize = 16 << 20;
 
 pub struct MemoryBuilder<'a> {
     runtime: &'a Runtime,
@@ -46,82 +52,219 @@ impl<'a> MemoryBuilder<'a> {
         self
     }
 
+    pub(crate) fn runtime(&self) -> &'a Runtime {
+        self.runtime
+    }
+
     pub fn build(&mut self) -> Result<Memory> {
         Memory::new(self.runtime, &self.allocate_info)
     }
+    pub fn build_scoped(&mut self) -> Result<scoped::Memory<'a>> {
+        scoped::Memory::new(self.runtime, &self.allocate_info)
+    }
 }
 
 struct Memory_ {
     runtime: Runtime,
     memory: TiMemory,
     allocate_info: TiMemoryAllocateInfo,
+    mapped: AtomicBool,
 }
 impl Memory_ {
     pub fn new(runtime: &Runtime, allocate_info: &TiMemoryAllocateInfo) -> Result<Self> {
+        let _guard = runtime.enter()?;
+        runtime.memory_tracker().reserve_memory(allocate_info)?;
         let memory = unsafe {
             ti_allocate_memory(runtime.runtime(), allocate_info)
         };
-        get_last_error()?;
+        if let Err(e) = get_last_error() {
+            runtime.memory_tracker().release_memory(allocate_info);
+            return Err(e.with_context("allocate memory", None));
+        }
         let out = Memory_ {
             runtime: runtime.clone(),
             memory,
-            allocate_info: allocate_info.clone()
+            allocate_info: *allocate_info,
+            mapped: AtomicBool::new(false),
         };
         Ok(out)
     }
 }
 impl Drop for Memory_ {
     fn drop(&mut self) {
-        unsafe {
-            ti_free_memory(self.runtime.runtime(), self.memory);
-        }
+        let memory = self.memory;
+        self.runtime.release(move |runtime| unsafe {
+            ti_free_memory(runtime, memory);
+        });
+        self.runtime.memory_tracker().release_memory(&self.allocate_info);
     }
 }
 
 #[derive(Clone)]
 pub struct Memory {
-    inner: Rc<Memory_>,
+    inner: Shared<Memory_>,
 }
 impl Memory {
     pub fn new(runtime: &Runtime, allocate_info: &TiMemoryAllocateInfo) -> Result<Self> {
         let inner = Memory_::new(runtime, allocate_info)?;
         let out = Memory {
-            inner: Rc::new(inner),
+            inner: Shared::new(inner),
         };
         Ok(out)
     }
 
-    pub fn map<T>(&self) -> Result<MappedMemory<'_, T>> {
+    pub fn slice(&self, offset: usize, size: usize) -> Result<MemorySlice<'_>> {
+        MemorySlice::new(self, offset, size)
+    }
+    pub fn as_slice(&self) -> MemorySlice<'_> {
+        MemorySlice { memory: self, offset: 0, size: self.size() }
+    }
+
+    pub fn map<T: Pod>(&self) -> Result<MappedMemory<'_, T>> {
         MappedMemory::new(self)
     }
+    pub fn map_mut<T: Pod>(&self) -> Result<MappedMemoryMut<'_, T>> {
+        MappedMemoryMut::new(self)
+    }
 
-    pub fn read<T: Clone>(&self, dst: &mut [T]) -> Result<()> {
+    fn check_granularity(what: &'static str, value: usize, granularity: usize) -> Result<()> {
+        if !value.is_multiple_of(granularity) {
+            return Err(BindingError::Unaligned { what, value, granularity }.into());
+        }
+        Ok(())
+    }
+    fn check_elem_granularity<T>(&self, what: &'static str, value: usize) -> Result<()> {
+        Self::check_granularity(what, value, std::mem::size_of::<T>())
+    }
+    fn check_range<T>(&self, offset: usize, len: usize) -> Result<()> {
+        Self::check_granularity("offset", offset, std::mem::align_of::<T>())?;
+        let end = std::mem::size_of::<T>().checked_mul(len)
+            .and_then(|x| x.checked_add(offset))
+            .filter(|x| *x <= self.size());
+        if end.is_none() {
+            return Err(BindingError::OutOfRange(format!(
+                "{} elements at offset {} are out of the memory size {}",
+                len, offset, self.size())).into());
+        }
+        Ok(())
+    }
+    fn check_whole<T>(&self, what: &'static str, len: usize) -> Result<()> {
+        self.check_elem_granularity::<T>("memory size", self.size())?;
+        let size = std::mem::size_of::<T>() * len;
+        if size != self.size() {
+            return Err(BindingError::SizeMismatch {
+                what,
+                expected: self.size(),
+                actual: size,
+            }.into());
+        }
+        Ok(())
+    }
+
+    pub fn read<T: Pod>(&self, dst: &mut [T]) -> Result<()> {
+        self.check_whole::<T>("read destination", dst.len())?;
+        self.read_at(0, dst)
+    }
+    pub fn read_at<T: Pod>(&self, offset: usize, dst: &mut [T]) -> Result<()> {
         if !self.host_read() {
-            return Err(Error::InvalidState("attempting to map non-host-readable memory"));
+            return Err(BindingError::NotHostReadable.into());
         }
-        let mapped = MappedMemory::new(self)?;
-        let len = self.size() as usize / std::mem::size_of::<T>();
-        debug_assert_eq!(dst.len(), len);
+        self.check_range::<T>(offset, dst.len())?;
+        let mapping = Mapping::new(self)?;
         let src = unsafe {
-            std::slice::from_raw_parts(mapped.ptr(), len)
+            std::slice::from_raw_parts(mapping.ptr.add(offset) as *const T, dst.len())
         };
-        dst.clone_from_slice(src);
+        dst.copy_from_slice(src);
         Ok(())
     }
-    pub fn write<T: Clone>(&self, src: &[T]) -> Result<()> {
-        if !self.host_write() {
-            return Err(Error::InvalidState("attempting to map non-host-writable memory"));
+    pub fn to_vec<T: Pod>(&self) -> Result<Vec<T>> {
+        if !self.host_read() {
+            return Err(BindingError::NotHostReadable.into());
         }
-        let mapped = MappedMemory::<T>::new(self)?;
+        self.check_elem_granularity::<T>("memory size", self.size())?;
+        let mapping = Mapping::new(self)?;
         let len = self.size() / std::mem::size_of::<T>();
-        debug_assert_eq!(src.len(), len);
+        let src = unsafe {
+            std::slice::from_raw_parts(mapping.ptr as *const T, len)
+        };
+        Ok(src.to_vec())
+    }
+    pub fn write<T: Pod>(&self, src: &[T]) -> Result<()> {
+        self.check_whole::<T>("write source", src.len())?;
+        self.write_at(0, src)
+    }
+    pub fn write_at<T: Pod>(&self, offset: usize, src: &[T]) -> Result<()> {
+        if !self.host_write() {
+            return Err(BindingError::NotHostWritable.into());
+        }
+        self.check_range::<T>(offset, src.len())?;
+        let mapping = Mapping::new(self)?;
         let dst = unsafe {
-            std::slice::from_raw_parts_mut(mapped.ptr_mut(), len)
+            std::slice::from_raw_parts_mut(mapping.ptr.add(offset) as *mut T, src.len())
         };
-        dst.clone_from_slice(src);
+        dst.copy_from_slice(src);
         Ok(())
     }
 
+    // Transfers for device-only memory are staged through host-visible
+    // memory allocated for each transfer and freed after it. Transfers larger
+    // than `STAGING_SIZE` are copied in chunks so the staging memory stays
+    // bounded.
+    fn staging_memory<T>(&self, len: usize) -> Result<(Memory, usize)> {
+        let chunk_len = (STAGING_SIZE / std::mem::size_of::<T>().max(1)).clamp(1, len.max(1));
+        let staging = self.inner.runtime.allocate_memory()
+            .size(chunk_len * std::mem::size_of::<T>())
+            .host_read(true)
+            .host_write(true)
+            .build()?;
+        Ok((staging, chunk_len))
+    }
+    pub fn upload<T: Pod>(&self, src: &[T]) -> Result<()> {
+        if std::mem::size_of_val(src) != self.size() {
+            return Err(BindingError::SizeMismatch {
+                what: "upload source",
+                expected: self.size(),
+                actual: std::mem::size_of_val(src),
+            }.into());
+        }
+        if self.host_write() {
+            return self.write(src);
+        }
+        let (staging, chunk_len) = self.staging_memory::<T>(src.len())?;
+        for (i, chunk) in src.chunks(chunk_len).enumerate() {
+            let size = std::mem::size_of_val(chunk);
+            staging.write_at(0, chunk)?;
+            self.slice(i * chunk_len * std::mem::size_of::<T>(), size)?
+                .copy_from(&staging.slice(0, size)?)?;
+            self.inner.runtime.wait()?;
+        }
+        Ok(())
+    }
+    pub fn download<T: Pod>(&self, dst: &mut [T]) -> Result<()> {
+        if std::mem::size_of_val(dst) != self.size() {
+            return Err(BindingError::SizeMismatch {
+                what: "download destination",
+                expected: self.size(),
+                actual: std::mem::size_of_val(dst),
+            }.into());
+        }
+        if self.host_read() {
+            return self.read(dst);
+        }
+        let (staging, chunk_len) = self.staging_memory::<T>(dst.len())?;
+        for (i, chunk) in dst.chunks_mut(chunk_len).enumerate() {
+            let size = std::mem::size_of_val(chunk);
+            staging.slice(0, size)?
+                .copy_from(&self.slice(i * chunk_len * std::mem::size_of::<T>(), size)?)?;
+            self.inner.runtime.wait()?;
+            staging.read_at(0, chunk)?;
+        }
+        Ok(())
+    }
+
+    pub(crate) fn parent_runtime(&self) -> &Runtime {
+        &self.inner.runtime
+    }
     pub fn runtime(&self) -> TiRuntime {
         self.inner.runtime.runtime()
     }
@@ -143,31 +286,197 @@ impl Memory {
     }
 }
 
-pub struct MappedMemory<'a, T>(&'a Memory, *mut T);
-impl<'a, T> MappedMemory<'a, T> {
-    pub fn new(memory: &'a Memory) -> Result<Self> {
-        debug_assert!(memory.size() % std::mem::size_of::<T>() == 0);
-        let mapped = unsafe {
-            ti_map_memory(memory.runtime(), memory.memory()) as *mut T
+#[derive(Clone, Copy)]
+pub struct MemorySlice<'a> {
+    memory: &'a Memory,
+    offset: usize,
+    size: usize,
+}
+impl<'a> MemorySlice<'a> {
+    pub fn new(memory: &'a Memory, offset: usize, size: usize) -> Result<Self> {
+        let end = offset.checked_add(size)
+            .ok_or_else(|| BindingError::OutOfRange("memory slice range overflows".to_owned()))?;
+        if end > memory.size() {
+            return Err(BindingError::OutOfRange(format!(
+                "memory slice [{}, {}) is out of the memory size {}",
+                offset, end, memory.size())).into());
+        }
+        Ok(MemorySlice { memory, offset, size })
+    }
+
+    pub(crate) fn new_unchecked(memory: &'a Memory, offset: usize, size: usize) -> Self {
+        debug_assert!(offset + size <= memory.size());
+        MemorySlice { memory, offset, size }
+    }
+
+    pub fn memory(&self) -> &Memory {
+        self.memory
+    }
+    pub fn offset(&self) -> usize {
+        self.offset
+    }
+    pub fn size(&self) -> usize {
+        self.size
+    }
+    fn range(&self) -> Result<(usize, usize)> {
+        let end = self.offset.checked_add(self.size)
+            .ok_or_else(|| BindingError::OutOfRange("memory slice range overflows".to_owned()))?;
+        Ok((self.offset, end))
+    }
+    pub fn memory_slice(&self) -> TiMemorySlice {
+        TiMemorySlice {
+            memory: self.memory.memory(),
+            offset: self.offset as u64,
+            size: self.size as u64,
+        }
+    }
+
+    pub(crate) fn check_copy_from(&self, src: &MemorySlice<'_>) -> Result<()> {
+        if self.memory.runtime() != src.memory.runtime() {
+            return Err(BindingError::RuntimeMismatch.into());
+        }
+        if self.size != src.size {
+            return Err(BindingError::SizeMismatch {
+                what: "memory slice",
+                expected: self.size,
+                actual: src.size,
+            }.into());
+        }
+        // Device-to-device copies within one allocation are undefined if the
+        // ranges overlap.
+        let (dst_range, src_range) = (self.range()?, src.range()?);
+        let overlaps = dst_range.0 < src_range.1 && src_range.0 < dst_range.1;
+        if self.memory.memory() == src.memory.memory() && overlaps {
+            return Err(BindingError::OverlappingCopy { dst: dst_range, src: src_range }.into());
+        }
+        Ok(())
+    }
+    pub fn copy_from(&self, src: &MemorySlice<'_>) -> Result<()> {
+        self.check_copy_from(src)?;
+        let dst = self.memory_slice();
+        let src = src.memory_slice();
+        let _guard = self.memory.parent_runtime().enter()?;
+        unsafe {
+            ti_copy_memory_device_to_device(self.memory.runtime(), &dst, &src);
+        }
+        get_last_error()
+            .map_err(|e| e.with_context("copy memory", None))?;
+        Ok(())
+    }
+}
+impl<'a> From<&'a Memory> for MemorySlice<'a> {
+    fn from(memory: &'a Memory) -> Self {
+        memory.as_slice()
+    }
+}
+
+// A memory can only be mapped once at a time, otherwise a `MappedMemoryMut`
+// could alias another mapping of the same memory.
+struct Mapping<'a> {
+    memory: &'a Memory,
+    ptr: *mut u8,
+}
+impl<'a> Mapping<'a> {
+    fn new(memory: &'a Memory) -> Result<Self> {
+        let _guard = memory.parent_runtime().enter()?;
+        if memory.inner.mapped.swap(true, Ordering::Acquire) {
+            return Err(BindingError::AlreadyMapped.into());
+        }
+        let ptr = unsafe {
+            ti_map_memory(memory.runtime(), memory.memory()) as *mut u8
         };
-        get_last_error()?;
-        Ok(MappedMemory(memory, mapped))
+        if let Err(e) = get_last_error() {
+            memory.inner.mapped.store(false, Ordering::Release);
+            return Err(e.with_context("map memory", None));
+        }
+        Ok(Mapping { memory, ptr })
+    }
+}
+impl<'a> Drop for Mapping<'a> {
+    fn drop(&mut self) {
+        let memory = self.memory.memory();
+        self.memory.parent_runtime().release(move |runtime| unsafe {
+            ti_unmap_memory(runtime, memory);
+        });
+        self.memory.inner.mapped.store(false, Ordering::Release);
+    }
+}
+
+fn mapped_len<T>(memory: &Memory) -> Result<usize> {
+    memory.check_elem_granularity::<T>("memory size", memory.size())?;
+    Ok(memory.size() / std::mem::size_of::<T>())
+}
+
+pub struct MappedMemory<'a, T> {
+    mapping: Mapping<'a>,
+    len: usize,
+    phantom: PhantomData<&'a [T]>,
+}
+impl<'a, T: Pod> MappedMemory<'a, T> {
+    pub fn new(memory: &'a Memory) -> Result<Self> {
+        if !memory.host_read() {
+            return Err(BindingError::NotHostReadable.into());
+        }
+        let len = mapped_len::<T>(memory)?;
+        let mapping = Mapping::new(memory)?;
+        Ok(MappedMemory { mapping, len, phantom: PhantomData })
     }
 
     pub fn memory(&self) -> &Memory {
-        self.0
+        self.mapping.memory
     }
     pub fn ptr(&self) -> *const T {
-        self.1
+        self.mapping.ptr as *const T
     }
-    pub fn ptr_mut(&self) -> *mut T {
-        self.1
+}
+impl<'a, T: Pod> Deref for MappedMemory<'a, T> {
+    type Target = [T];
+    fn deref(&self) -> &[T] {
+        unsafe {
+            std::slice::from_raw_parts(self.ptr(), self.len)
+        }
     }
 }
-impl<'a, T> Drop for MappedMemory<'a, T> {
-    fn drop(&mut self) {
+
+// Any bit pattern is a valid `Pod`, so reading back memory that is only
+// host-writable is sound even though its content is unspecified.
+pub struct MappedMemoryMut<'a, T> {
+    mapping: Mapping<'a>,
+    len: usize,
+    phantom: PhantomData<&'a mut [T]>,
+}
+impl<'a, T: Pod> MappedMemoryMut<'a, T> {
+    pub fn new(memory: &'a Memory) -> Result<Self> {
+        if !memory.host_write() {
+            return Err(BindingError::NotHostWritable.into());
+        }
+        let len = mapped_len::<T>(memory)?;
+        let mapping = Mapping::new(memory)?;
+        Ok(MappedMemoryMut { mapping, len, phantom: PhantomData })
+    }
+
+    pub fn memory(&self) -> &Memory {
+        self.mapping.memory
+    }
+    pub fn ptr(&self) -> *const T {
+        self.mapping.ptr as *const T
+    }
+    pub fn ptr_mut(&mut self) -> *mut T {
+        self.mapping.ptr as *mut T
+    }
+}
+impl<'a, T: Pod> Deref for MappedMemoryMut<'a, T> {
+    type Target = [T];
+    fn deref(&self) -> &[T] {
+        unsafe {
+            std::slice::from_raw_parts(self.ptr(), self.len)
+        }
+    }
+}
+impl<'a, T: Pod> DerefMut for MappedMemoryMut<'a, T> {
+    fn deref_mut(&mut self) -> &mut [T] {
         unsafe {
-            ti_unmap_memory(self.memory().runtime(), self.memory().memory());
+            std::slice::from_raw_parts_mut(self.ptr_mut(), self.len)
         }
     }
 }
@@ -0,0 +1,295 @@
+use crate::sync::Shared;
+use std::sync::Mutex;
+use bytemuck::Pod;
+use taichi_sys::{TiMemoryAllocateInfo, TI_FALSE, TI_TRUE, TiMemoryUsageFlags};
+
+use crate::{BindingError, Result, Runtime, Memory, MemorySlice, NdArray, Element};
+use crate::ndarray::NdArrayBuilder;
+
+pub struct MemoryPoolBuilder<'a> {
+    runtime: &'a Runtime,
+    allocate_info: TiMemoryAllocateInfo,
+    block_size: usize,
+    alignment: usize,
+}
+impl<'a> MemoryPoolBuilder<'a> {
+    pub fn new(runtime: &'a Runtime) -> Self {
+        let allocate_info = TiMemoryAllocateInfo {
+            size: 0,
+            host_write: TI_FALSE,
+            host_read: TI_FALSE,
+            export_sharing: TI_FALSE,
+            usage: TiMemoryUsageFlags::STORAGE_BIT
+        };
+        MemoryPoolBuilder {
+            runtime,
+            allocate_info,
+            block_size: 16 << 20,
+            alignment: 256,
+        }
+    }
+    pub fn block_size(&mut self, block_size: usize) -> &mut Self {
+        self.block_size = block_size;
+        self
+    }
+    // Sub-allocation offsets and sizes are rounded up to this power of two.
+    pub fn alignment(&mut self, alignment: usize) -> &mut Self {
+        self.alignment = alignment;
+        self
+    }
+    pub fn host_read(&mut self, value: bool) -> &mut Self {
+        self.allocate_info.host_read = if value { TI_TRUE } else { TI_FALSE };
+        self
+    }
+    pub fn host_write(&mut self, value: bool) -> &mut Self {
+        self.allocate_info.host_write = if value { TI_TRUE } else { TI_FALSE };
+        self
+    }
+    pub fn usage(&mut self, usage: TiMemoryUsageFlags) -> &mut Self {
+        self.allocate_info.usage = usage;
+        self
+    }
+
+    pub fn build(&mut self) -> Result<MemoryPool> {
+        MemoryPool::new(self.runtime, &self.allocate_info, self.block_size, self.alignment)
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct MemoryPoolStats {
+    pub block_count: usize,
+    pub reserved_size: usize,
+    pub used_size: usize,
+    pub peak_used_size: usize,
+    pub allocation_count: usize,
+}
+
+struct Block {
+    id: usize,
+    memory: Memory,
+    // Free `(offset, size)` ranges sorted by offset, never adjacent.
+    free_ranges: Vec<(usize, usize)>,
+}
+impl Block {
+    fn allocate(&mut self, size: usize) -> Option<usize> {
+        let i = self.free_ranges.iter().position(|(_, free_size)| *free_size >= size)?;
+        let (offset, free_size) = self.free_ranges[i];
+        if free_size == size {
+            self.free_ranges.remove(i);
+        } else {
+            self.free_ranges[i] = (offset + size, free_size - size);
+        }
+        Some(offset)
+    }
+    fn free(&mut self, offset: usize, size: usize) {
+        let i = self.free_ranges.partition_point(|(x, _)| *x < offset);
+        self.free_ranges.insert(i, (offset, size));
+        if i + 1 < self.free_ranges.len() && offset + size == self.free_ranges[i + 1].0 {
+            self.free_ranges[i].1 += self.free_ranges.remove(i + 1).1;
+        }
+        if i > 0 && self.free_ranges[i - 1].0 + self.free_ranges[i - 1].1 == offset {
+            self.free_ranges[i - 1].1 += self.free_ranges.remove(i).1;
+        }
+    }
+    fn is_unused(&self) -> bool {
+        self.free_ranges == [(0, self.memory.size())]
+    }
+}
+
+#[derive(Default)]
+struct PoolState {
+    blocks: Vec<Block>,
+    next_block_id: usize,
+    stats: MemoryPoolStats,
+}
+impl PoolState {
+    fn update_block_stats(&mut self) {
+        self.stats.block_count = self.blocks.len();
+        self.stats.reserved_size = self.blocks.iter().map(|x| x.memory.size()).sum();
+    }
+}
+
+struct MemoryPool_ {
+    runtime: Runtime,
+    allocate_info: TiMemoryAllocateInfo,
+    block_size: usize,
+    alignment: usize,
+    state: Mutex<PoolState>,
+}
+
+#[derive(Clone)]
+pub struct MemoryPool {
+    inner: Shared<MemoryPool_>,
+}
+impl MemoryPool {
+    pub fn new(runtime: &Runtime, allocate_info: &TiMemoryAllocateInfo, block_size: usize, alignment: usize) -> Result<Self> {
+        if !alignment.is_power_of_two() {
+            return Err(BindingError::OutOfRange(format!(
+                "memory pool alignment {} is not a power of two", alignment)).into());
+        }
+        if block_size == 0 {
+            return Err(BindingError::OutOfRange("memory pool block size is zero".to_owned()).into());
+        }
+        let inner = MemoryPool_ {
+            runtime: runtime.clone(),
+            allocate_info: *allocate_info,
+            block_size,
+            alignment,
+            state: Mutex::new(PoolState::default()),
+        };
+        Ok(MemoryPool { inner: Shared::new(inner) })
+    }
+
+    pub fn allocate(&self, size: usize) -> Result<PoolAllocation> {
+        if size == 0 {
+            return Err(BindingError::OutOfRange("sub-allocation size is zero".to_owned()).into());
+        }
+        let reserved_size = size.checked_next_multiple_of(self.inner.alignment)
+            .ok_or_else(|| BindingError::OutOfRange(format!(
+                "sub-allocation size {} overflows", size)))?;
+
+        let mut state = self.inner.state.lock().unwrap();
+        let found = state.blocks.iter_mut()
+            .find_map(|block| block.allocate(reserved_size).map(|offset| (block.id, block.memory.clone(), offset)));
+        let (block_id, memory, offset) = match found {
+            Some(x) => x,
+            None => {
+                // Oversized requests get a dedicated block of their own.
+                let mut allocate_info = self.inner.allocate_info;
+                allocate_info.size = reserved_size.max(self.inner.block_size) as u64;
+                let memory = Memory::new(&self.inner.runtime, &allocate_info)?;
+                let block_id = state.next_block_id;
+                let mut block = Block {
+                    id: block_id,
+                    free_ranges: vec![(0, memory.size())],
+                    memory: memory.clone(),
+                };
+                let offset = block.allocate(reserved_size).unwrap();
+                state.next_block_id += 1;
+                state.blocks.push(block);
+                state.update_block_stats();
+                (block_id, memory, offset)
+            }
+        };
+
+        let stats = &mut state.stats;
+        stats.used_size += reserved_size;
+        stats.peak_used_size = stats.peak_used_size.max(stats.used_size);
+        stats.allocation_count += 1;
+
+        Ok(PoolAllocation {
+            pool: self.clone(),
+            block_id,
+            memory,
+            offset,
+            size,
+            reserved_size,
+        })
+    }
+
+    fn free(&self, block_id: usize, offset: usize, reserved_size: usize) {
+        let mut state = self.inner.state.lock().unwrap();
+        if let Some(block) = state.blocks.iter_mut().find(|x| x.id == block_id) {
+            block.free(offset, reserved_size);
+        }
+        state.stats.used_size -= reserved_size;
+        state.stats.allocation_count -= 1;
+    }
+
+    // Takes a block out of the pool, leaving its memory to whoever holds it.
+    fn detach(&self, block_id: usize) {
+        let mut state = self.inner.state.lock().unwrap();
+        state.blocks.retain(|x| x.id != block_id);
+        state.update_block_stats();
+    }
+
+    // Releases blocks without any live sub-allocation back to the driver.
+    pub fn trim(&self) {
+        let mut state = self.inner.state.lock().unwrap();
+        state.blocks.retain(|x| !x.is_unused());
+        state.update_block_stats();
+    }
+
+    pub fn stats(&self) -> MemoryPoolStats {
+        self.inner.state.lock().unwrap().stats
+    }
+    pub fn block_size(&self) -> usize {
+        self.inner.block_size
+    }
+    pub fn alignment(&self) -> usize {
+        self.inner.alignment
+    }
+}
+
+// ND-array arguments are bound by `TiMemory` handle without an offset, so
+// sub-allocations can only be used through memory slices and host access,
+// unless they cover a whole block. See `PoolAllocation::into_ndarray`.
+pub struct PoolAllocation {
+    pool: MemoryPool,
+    block_id: usize,
+    memory: Memory,
+    offset: usize,
+    size: usize,
+    reserved_size: usize,
+}
+impl PoolAllocation {
+    pub fn slice(&self) -> MemorySlice<'_> {
+        MemorySlice::new_unchecked(&self.memory, self.offset, self.size)
+    }
+
+    pub fn read<T: Pod>(&self, dst: &mut [T]) -> Result<()> {
+        self.check_size("read destination", std::mem::size_of_val(dst))?;
+        self.memory.read_at(self.offset, dst)
+    }
+    pub fn write<T: Pod>(&self, src: &[T]) -> Result<()> {
+        self.check_size("write source", std::mem::size_of_val(src))?;
+        self.memory.write_at(self.offset, src)
+    }
+    // Only an allocation spanning its whole block can back an ND-array, which
+    // is the case for a dedicated block when the size is a multiple of the
+    // alignment and at least the block size. The block then leaves the pool
+    // with the ND-array.
+    pub fn into_ndarray<T: Element, S: AsRef<[u32]>>(self, shape: S) -> Result<NdArray<T>> {
+        if self.offset != 0 || self.size != self.memory.size() {
+            return Err(BindingError::OutOfRange(format!(
+                "sub-allocation [{}, +{}) doesn't cover its whole memory block of {} bytes",
+                self.offset, self.size, self.memory.size())).into());
+        }
+        let ndarray = NdArrayBuilder::<T>::new(self.memory.parent_runtime())
+            .shape(shape)
+            .build_with_memory(self.memory.clone())?;
+        self.pool.detach(self.block_id);
+        Ok(ndarray)
+    }
+
+    fn check_size(&self, what: &'static str, size: usize) -> Result<()> {
+        if size != self.size {
+            return Err(BindingError::SizeMismatch {
+                what,
+                expected: self.size,
+                actual: size,
+            }.into());
+        }
+        Ok(())
+    }
+
+    pub fn memory(&self) -> &Memory {
+        &self.memory
+    }
+    pub fn offset(&self) -> usize {
+        self.offset
+    }
+    pub fn size(&self) -> usize {
+        self.size
+    }
+}
+impl Drop for PoolAllocation {
+    fn drop(&mut self) {
+        self.pool.free(self.block_id, self.offset, self.reserved_size);
+    }
+}
+impl<'a> From<&'a PoolAllocation> for MemorySlice<'a> {
+    fn from(allocation: &'a PoolAllocation) -> Self {
+        allocation.slice()
+    }
+}
@@ -0,0 +1,132 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use taichi_sys::{TiMemoryAllocateInfo, TiMemoryUsageFlags, TiImageAllocateInfo, TiFormat};
+
+use crate::{BindingError, Result};
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct AllocationStats {
+    pub count: usize,
+    pub size: usize,
+}
+impl AllocationStats {
+    fn add(&mut self, size: usize) {
+        self.count += 1;
+        self.size += size;
+    }
+    fn remove(&mut self, size: usize) {
+        self.count -= 1;
+        self.size -= size;
+    }
+}
+
+// Image sizes are estimated from the format and the full mip chain; drivers
+// may pad or compress them differently.
+#[derive(Debug, Clone, Default)]
+pub struct MemoryReport {
+    pub memory: AllocationStats,
+    pub image: AllocationStats,
+    pub memory_by_usage: HashMap<TiMemoryUsageFlags, AllocationStats>,
+    pub image_by_format: HashMap<TiFormat, AllocationStats>,
+}
+impl MemoryReport {
+    pub fn total_size(&self) -> usize {
+        self.memory.size + self.image.size
+    }
+}
+
+fn texel_size(format: TiFormat) -> usize {
+    use TiFormat::*;
+    match format {
+        Unknown => 0,
+        R8 | R8U | R8I => 1,
+        Rg8 | Rg8U | Rg8I | R16 | R16U | R16I | R16F | Depth16 => 2,
+        Rgb16 | Rgb16U | Rgb16I | Rgb16F => 6,
+        Rgba8 | Rgba8Srgb | Bgra8 | Bgra8Srgb | Rgba8U | Rgba8I |
+        Rg16 | Rg16U | Rg16I | Rg16F |
+        R32U | R32I | R32F | Depth24Stencil8 | Depth32F => 4,
+        Rgba16 | Rgba16U | Rgba16I | Rgba16F | Rg32U | Rg32I | Rg32F => 8,
+        Rgb32U | Rgb32I | Rgb32F => 12,
+        Rgba32U | Rgba32I | Rgba32F => 16,
+    }
+}
+pub(crate) fn image_size(allocate_info: &TiImageAllocateInfo) -> usize {
+    let extent = &allocate_info.extent;
+    let texel_count = (0..allocate_info.mip_level_count.max(1))
+        .map(|mip| {
+            let width = (extent.width >> mip).max(1) as usize;
+            let height = (extent.height >> mip).max(1) as usize;
+            let depth = (extent.depth >> mip).max(1) as usize;
+            width * height * depth
+        })
+        .sum::<usize>();
+    texel_count * extent.array_layer_count.max(1) as usize * texel_size(allocate_info.format)
+}
+
+#[derive(Default)]
+pub(crate) struct MemoryTracker {
+    report: Mutex<MemoryReport>,
+    budget: Mutex<Option<usize>>,
+}
+impl MemoryTracker {
+    fn check_budget(&self, report: &MemoryReport, requested: usize) -> Result<()> {
+        if let Some(budget) = *self.budget.lock().unwrap() {
+            let used = report.total_size();
+            if used + requested > budget {
+                return Err(BindingError::BudgetExceeded { requested, used, budget }.into());
+            }
+        }
+        Ok(())
+    }
+
+    // Allocations are accounted before they reach the driver so that a
+    // budget is enforced up front. Call `release_*` if the allocation fails.
+    pub fn reserve_memory(&self, allocate_info: &TiMemoryAllocateInfo) -> Result<()> {
+        let size = allocate_info.size as usize;
+        let mut report = self.report.lock().unwrap();
+        self.check_budget(&report, size)?;
+        report.memory.add(size);
+        report.memory_by_usage.entry(allocate_info.usage).or_default().add(size);
+        Ok(())
+    }
+    pub fn release_memory(&self, allocate_info: &TiMemoryAllocateInfo) {
+        let size = allocate_info.size as usize;
+        let mut report = self.report.lock().unwrap();
+        report.memory.remove(size);
+        if let Some(stats) = report.memory_by_usage.get_mut(&allocate_info.usage) {
+            stats.remove(size);
+            if stats.count == 0 {
+                report.memory_by_usage.remove(&allocate_info.usage);
+            }
+        }
+    }
+    pub fn reserve_image(&self, allocate_info: &TiImageAllocateInfo) -> Result<()> {
+        let size = image_size(allocate_info);
+        let mut report = self.report.lock().unwrap();
+        self.check_budget(&report, size)?;
+        report.image.add(size);
+        report.image_by_format.entry(allocate_info.format).or_default().add(size);
+        Ok(())
+    }
+    pub fn release_image(&self, allocate_info: &TiImageAllocateInfo) {
+        let size = image_size(allocate_info);
+        let mut report = self.report.lock().unwrap();
+        report.image.remove(size);
+        if let Some(stats) = report.image_by_format.get_mut(&allocate_info.format) {
+            stats.remove(size);
+            if stats.count == 0 {
+                report.image_by_format.remove(&allocate_info.format);
+            }
+        }
+    }
+
+    pub fn report(&self) -> MemoryReport {
+        self.report.lock().unwrap().clone()
+    }
+    pub fn budget(&self) -> Option<usize> {
+        *self.budget.lock().unwrap()
+    }
+    pub fn set_budget(&self, budget: Option<usize>) {
+        *self.budget.lock().unwrap() = budget;
+    }
+}
@@ -0,0 +1,99 @@
+use std::borrow::Cow;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use taichi_sys::TiArch;
+
+use crate::{BindingError, Error, Result, Runtime, AotModule};
+
+pub(crate) fn arch_from_name(name: &str) -> Option<TiArch> {
+    let arch = match name {
+        "vulkan" => TiArch::Vulkan,
+        "metal" => TiArch::Metal,
+        "cuda" => TiArch::Cuda,
+        "x64" => TiArch::X64,
+        "arm64" => TiArch::Arm64,
+        "opengl" => TiArch::Opengl,
+        "gles" => TiArch::Gles,
+        _ => return None,
+    };
+    Some(arch)
+}
+
+#[derive(Debug, Clone)]
+pub enum ModuleSource {
+    Dir(PathBuf),
+    TcmFile(PathBuf),
+    Tcm(Cow<'static, [u8]>),
+}
+
+// The same Taichi program compiled for several archs, of which the variant
+// matching the runtime arch is loaded.
+#[derive(Debug, Clone, Default)]
+pub struct ModuleBundle {
+    variants: BTreeMap<TiArch, ModuleSource>,
+}
+impl ModuleBundle {
+    pub fn new() -> Self {
+        Default::default()
+    }
+    // Collects subdirectories and `.tcm` files named after an arch, e.g.
+    // `vulkan/` and `metal.tcm`. Other entries are ignored.
+    pub fn from_arch_dirs<P: AsRef<Path>>(root: P) -> Result<Self> {
+        let root = root.as_ref();
+        let io_error = |e| Error::from(BindingError::from(e))
+            .with_context("read module bundle", Some(&root.display().to_string()));
+        let mut out = ModuleBundle::new();
+        let entries = std::fs::read_dir(root)
+            .map_err(io_error)?;
+        for entry in entries {
+            let path = entry.map_err(io_error)?.path();
+            let is_tcm = path.extension().is_some_and(|x| x == "tcm");
+            let arch = path.file_stem()
+                .and_then(|x| x.to_str())
+                .and_then(arch_from_name);
+            match arch {
+                Some(arch) if path.is_dir() => out.add_dir(arch, path),
+                Some(arch) if is_tcm => out.add_tcm_file(arch, path),
+                _ => continue,
+            };
+        }
+        Ok(out)
+    }
+
+    pub fn add(&mut self, arch: TiArch, source: ModuleSource) -> &mut Self {
+        self.variants.insert(arch, source);
+        self
+    }
+    pub fn add_dir<P: Into<PathBuf>>(&mut self, arch: TiArch, module_dir: P) -> &mut Self {
+        self.add(arch, ModuleSource::Dir(module_dir.into()))
+    }
+    pub fn add_tcm_file<P: Into<PathBuf>>(&mut self, arch: TiArch, path: P) -> &mut Self {
+        self.add(arch, ModuleSource::TcmFile(path.into()))
+    }
+    pub fn add_tcm<T: Into<Cow<'static, [u8]>>>(&mut self, arch: TiArch, tcm: T) -> &mut Self {
+        self.add(arch, ModuleSource::Tcm(tcm.into()))
+    }
+
+    pub fn archs(&self) -> Vec<TiArch> {
+        self.variants.keys().copied().collect()
+    }
+    pub fn source(&self, arch: TiArch) -> Option<&ModuleSource> {
+        self.variants.get(&arch)
+    }
+
+    pub fn load(&self, runtime: &Runtime) -> Result<AotModule> {
+        let arch = runtime.arch();
+        let source = self.source(arch)
+            .ok_or_else(|| BindingError::IncompatibleModule { arch, available: self.archs() })?;
+        match source {
+            ModuleSource::Dir(module_dir) => AotModule::load(runtime, module_dir),
+            ModuleSource::TcmFile(path) => {
+                let file = std::fs::File::open(path)
+                    .map_err(|e| Error::from(BindingError::from(e))
+                        .with_context("open tcm", Some(&path.display().to_string())))?;
+                AotModule::from_reader(runtime, file)
+            }
+            ModuleSource::Tcm(tcm) => AotModule::new(runtime, tcm),
+        }
+    }
+}
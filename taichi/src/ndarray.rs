@@ -1,9 +1,11 @@
 use std::marker::PhantomData;
 use taichi_sys::*;
 use crate::{
-    Result,
+    BindingError, Result,
+    element::Element,
+    scoped,
     runtime::{Runtime},
-    memory::{MappedMemory, Memory, MemoryBuilder},
+    memory::{MappedMemory, MappedMemoryMut, Memory, MemoryBuilder},
 };
 
 pub struct NdArrayBuilder<'a, T> {
@@ -11,9 +13,9 @@ pub struct NdArrayBuilder<'a, T> {
     ndarray: TiNdArray,
     phantom: PhantomData<T>,
 }
-impl<'a, T> NdArrayBuilder<'a, T> {
+impl<'a, T: Element> NdArrayBuilder<'a, T> {
     pub fn new(runtime: &'a Runtime) -> Self {
-        NdArrayBuilder {
+        let mut out = NdArrayBuilder {
             memory_builder: runtime.allocate_memory(),
             ndarray: TiNdArray {
                 memory: TiMemory::null(),
@@ -22,7 +24,9 @@ impl<'a, T> NdArrayBuilder<'a, T> {
                 elem_type: TiDataType::Unknown,
             },
             phantom: Default::default(),
-        }
+        };
+        out.elem_shape(T::elem_shape());
+        out
     }
 
     pub fn host_read(&mut self, value: bool) -> &mut Self {
@@ -58,70 +62,61 @@ impl<'a, T> NdArrayBuilder<'a, T> {
         self
     }
 
-    fn build_impl(&mut self, elem_type: TiDataType) -> Result<NdArray<T>> {
-        let mut size = std::mem::size_of::<T>();
-        for i in 0..self.ndarray.shape.dim_count as usize {
-            size *= self.ndarray.shape.dims[i] as usize;
+    fn memory_size(&self) -> Result<usize> {
+        for dim_count in [self.ndarray.shape.dim_count, self.ndarray.elem_shape.dim_count] {
+            if dim_count as usize > self.ndarray.shape.dims.len() {
+                return Err(BindingError::DimOverflow { dim_count: dim_count as usize }.into());
+            }
         }
-        for i in 0..self.ndarray.elem_shape.dim_count as usize {
-            size *= self.ndarray.elem_shape.dims[i] as usize;
+
+        // `T` may already cover the innermost element dimensions, e.g.
+        // `[f32; 3]` covers the trailing `3` in an element shape of `[4, 3]`.
+        let type_elem_shape = T::elem_shape();
+        let elem_shape = &self.ndarray.elem_shape.dims[..self.ndarray.elem_shape.dim_count as usize];
+        if !elem_shape.ends_with(&type_elem_shape) {
+            return Err(BindingError::Mismatch {
+                what: "element shape suffix",
+                expected: format!("{:?}", type_elem_shape),
+                actual: format!("{:?}", elem_shape),
+            }.into());
         }
+        let extra_elem_shape = &elem_shape[..elem_shape.len() - type_elem_shape.len()];
+
+        let shape = &self.ndarray.shape.dims[..self.ndarray.shape.dim_count as usize];
+        shape.iter()
+            .chain(extra_elem_shape)
+            .try_fold(std::mem::size_of::<T>(), |size, x| size.checked_mul(*x as usize))
+            .ok_or_else(|| BindingError::OutOfRange(format!(
+                "ndarray of shape {:?} and element shape {:?} overflows the address space",
+                shape, elem_shape)).into())
+    }
+
+    pub fn build(&mut self) -> Result<NdArray<T>> {
+        let size = self.memory_size()?;
         let memory = self.memory_builder
             .size(size)
             .build()?;
-        self.ndarray.elem_type = elem_type;
+        self.ndarray.elem_type = T::ELEM_TYPE;
         self.ndarray.memory = memory.memory();
         NdArray::<T>::new(memory, self.ndarray)
     }
-}
-impl<'a> NdArrayBuilder<'a, f32> {
-    pub fn build(&mut self) -> Result<NdArray<f32>> {
-        self.build_impl(TiDataType::F32)
-    }
-}
-impl<'a> NdArrayBuilder<'a, f64> {
-    pub fn build(&mut self) -> Result<NdArray<f64>> {
-        self.build_impl(TiDataType::F64)
-    }
-}
-impl<'a> NdArrayBuilder<'a, i8> {
-    pub fn build(&mut self) -> Result<NdArray<i8>> {
-        self.build_impl(TiDataType::I8)
-    }
-}
-impl<'a> NdArrayBuilder<'a, i16> {
-    pub fn build(&mut self) -> Result<NdArray<i16>> {
-        self.build_impl(TiDataType::I16)
-    }
-}
-impl<'a> NdArrayBuilder<'a, i32> {
-    pub fn build(&mut self) -> Result<NdArray<i32>> {
-        self.build_impl(TiDataType::I32)
-    }
-}
-impl<'a> NdArrayBuilder<'a, i64> {
-    pub fn build(&mut self) -> Result<NdArray<i64>> {
-        self.build_impl(TiDataType::I64)
-    }
-}
-impl<'a> NdArrayBuilder<'a, u8> {
-    pub fn build(&mut self) -> Result<NdArray<u8>> {
-        self.build_impl(TiDataType::U8)
-    }
-}
-impl<'a> NdArrayBuilder<'a, u16> {
-    pub fn build(&mut self) -> Result<NdArray<u16>> {
-        self.build_impl(TiDataType::U16)
-    }
-}
-impl<'a> NdArrayBuilder<'a, u32> {
-    pub fn build(&mut self) -> Result<NdArray<u32>> {
-        self.build_impl(TiDataType::U32)
+    // Binds existing memory that exactly fits the shape instead of allocating.
+    pub(crate) fn build_with_memory(&mut self, memory: Memory) -> Result<NdArray<T>> {
+        let size = self.memory_size()?;
+        if memory.size() != size {
+            return Err(BindingError::SizeMismatch {
+                what: "ndarray memory",
+                expected: size,
+                actual: memory.size(),
+            }.into());
+        }
+        self.ndarray.elem_type = T::ELEM_TYPE;
+        self.ndarray.memory = memory.memory();
+        NdArray::<T>::new(memory, self.ndarray)
     }
-}
-impl<'a> NdArrayBuilder<'a, u64> {
-    pub fn build(&mut self) -> Result<NdArray<u64>> {
-        self.build_impl(TiDataType::U64)
+    pub fn build_scoped(&mut self) -> Result<scoped::NdArray<'a, T>> {
+        let runtime = self.memory_builder.runtime();
+        Ok(scoped::NdArray::new(runtime, self.build()?))
     }
 }
 
@@ -130,7 +125,7 @@ pub struct NdArray<T> {
     ndarray: TiNdArray,
     phantom: PhantomData<T>,
 }
-impl<T> NdArray<T> {
+impl<T: Element> NdArray<T> {
     fn new(
         memory: Memory,
         ndarray: TiNdArray,
@@ -142,20 +137,51 @@ impl<T> NdArray<T> {
     pub fn map(&self) -> Result<MappedMemory<'_, T>> {
         self.memory.map()
     }
+    pub fn map_mut(&self) -> Result<MappedMemoryMut<'_, T>> {
+        self.memory.map_mut()
+    }
 
-    pub fn read<U: Clone>(&self, dst: &mut [U]) -> Result<()> {
+    pub fn read(&self, dst: &mut [T]) -> Result<()> {
         self.memory.read(dst)
     }
-    pub fn write<U: Clone>(&self, src: &[U]) -> Result<()> {
+    pub fn write(&self, src: &[T]) -> Result<()> {
         self.memory.write(src)
     }
 
-    pub fn to_vec<U: Clone + Default>(&self) -> Result<Vec<U>> {
-        let n = self.memory.size() as usize / std::mem::size_of::<U>();
-        let mut out = Vec::new();
-        out.resize(n, U::default());
-        self.read(&mut out)?;
-        Ok(out)
+    pub fn to_vec(&self) -> Result<Vec<T>> {
+        self.memory.to_vec()
+    }
+
+    pub fn upload(&self, src: &[T]) -> Result<()> {
+        self.memory.upload(src)
+    }
+    pub fn download(&self, dst: &mut [T]) -> Result<()> {
+        self.memory.download(dst)
+    }
+
+    pub fn copy_from(&self, src: &NdArray<T>) -> Result<()> {
+        if self.elem_type() != src.elem_type() {
+            return Err(BindingError::Mismatch {
+                what: "ndarray element type",
+                expected: format!("{:?}", self.elem_type()),
+                actual: format!("{:?}", src.elem_type()),
+            }.into());
+        }
+        if self.shape() != src.shape() {
+            return Err(BindingError::Mismatch {
+                what: "ndarray shape",
+                expected: format!("{:?}", self.shape()),
+                actual: format!("{:?}", src.shape()),
+            }.into());
+        }
+        if self.elem_shape() != src.elem_shape() {
+            return Err(BindingError::Mismatch {
+                what: "ndarray element shape",
+                expected: format!("{:?}", self.elem_shape()),
+                actual: format!("{:?}", src.elem_shape()),
+            }.into());
+        }
+        self.memory.as_slice().copy_from(&src.memory.as_slice())
     }
 
     pub fn memory(&self) -> &Memory {
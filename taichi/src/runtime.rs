@@ -1,36 +1,136 @@
-use std::rc::Rc;
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::{Arc, Mutex, MutexGuard};
 use taichi_sys::*;
 use crate::{
-    get_last_error, Result,
+    get_last_error, available_archs, BindingError, Result,
+    sync::{Shared, ThreadAffinity},
     aot_module::AotModule,
-    memory::MemoryBuilder,
-    ndarray::NdArrayBuilder, image::ImageBuilder, texture::TextureBuilder,
+    element::Element,
+    memory::{MemoryBuilder, MemorySlice},
+    memory_pool::MemoryPoolBuilder,
+    memory_report::{MemoryReport, MemoryTracker},
+    ndarray::NdArrayBuilder, image::ImageBuilder, sampler::SamplerBuilder, texture::TextureBuilder,
+    batch::Batch,
+    submission::WaitQueue,
+    scoped,
 };
 
-struct Runtime_ {
-    arch: TiArch,
+// GPU backends come first; CPU is the last resort for headless machines.
+const PREFERRED_ARCHS: [TiArch; 4] = [
+    TiArch::Vulkan,
+    TiArch::Metal,
+    TiArch::Cuda,
+    TiArch::X64,
+];
+
+type Deferred = Box<dyn FnOnce(TiRuntime) + Send>;
+
+pub(crate) struct RuntimeState {
+    thread_affinity: ThreadAffinity,
+}
+pub(crate) type RuntimeGuard<'a> = MutexGuard<'a, RuntimeState>;
+
+// The native runtime, shared with the waiter thread of submissions so that
+// it's destroyed by whichever lets go of it last. Every call into the C-API
+// is made while holding `state`, so calls made while the waiter thread is in
+// `ti_wait` block until it returns.
+pub(crate) struct RuntimeCore {
     runtime: TiRuntime,
+    state: Mutex<RuntimeState>,
+    // Frees of resources dropped while the runtime couldn't be entered.
+    deferred: Mutex<Vec<Deferred>>,
+    wait_queue: Arc<WaitQueue>,
 }
-impl Runtime_ {
-    fn new(arch: TiArch, device_index: u32) -> Result<Self> {
-        let runtime = unsafe {
-            ti_create_runtime(arch, device_index)
+impl RuntimeCore {
+    fn new(runtime: TiRuntime) -> Self {
+        let state = RuntimeState {
+            thread_affinity: ThreadAffinity::new(),
         };
-        get_last_error()?;
-        Ok(Runtime_ { arch, runtime })
+        RuntimeCore {
+            runtime,
+            state: Mutex::new(state),
+            deferred: Default::default(),
+            wait_queue: Default::default(),
+        }
+    }
+
+    fn run_deferred(&self) {
+        let deferred = std::mem::take(&mut *self.deferred.lock().unwrap());
+        for f in deferred {
+            f(self.runtime);
+        }
+    }
+    fn enter(&self) -> Result<RuntimeGuard<'_>> {
+        let state = self.state.lock().unwrap();
+        state.thread_affinity.check()?;
+        self.run_deferred();
+        Ok(state)
+    }
+    // Frees a resource now if the runtime can be entered from this thread,
+    // otherwise the next time it is entered from the bound thread. It never
+    // blocks, so it's safe in `Drop` even while the runtime is entered.
+    fn release(&self, f: Deferred) {
+        let state = self.state.try_lock().ok()
+            .filter(|x| x.thread_affinity.check().is_ok());
+        match state {
+            Some(_state) => {
+                self.run_deferred();
+                f(self.runtime);
+            }
+            None => self.deferred.lock().unwrap().push(f),
+        }
+    }
+
+    pub fn state(&self) -> &Mutex<RuntimeState> {
+        &self.state
+    }
+    pub fn wait_queue(&self) -> &Arc<WaitQueue> {
+        &self.wait_queue
+    }
+    #[cfg(test)]
+    pub fn deferred_count(&self) -> usize {
+        self.deferred.lock().unwrap().len()
+    }
+    pub fn runtime(&self) -> TiRuntime {
+        self.runtime
     }
 }
-impl Drop for Runtime_ {
+// Nothing else can use the runtime once the last handle is gone, so it's
+// destroyed on whichever thread lets go of it.
+impl Drop for RuntimeCore {
     fn drop(&mut self) {
+        self.wait_queue.close();
+        self.run_deferred();
         unsafe {
             ti_destroy_runtime(self.runtime)
         };
     }
 }
 
+struct Runtime_ {
+    arch: TiArch,
+    core: Arc<RuntimeCore>,
+    memory_tracker: MemoryTracker,
+}
+impl Runtime_ {
+    fn new(arch: TiArch, device_index: u32) -> Result<Self> {
+        let runtime = unsafe {
+            ti_create_runtime(arch, device_index)
+        };
+        get_last_error()
+            .map_err(|e| e.with_context("create runtime", Some(&format!("{:?}", arch))))?;
+        Ok(Runtime_ {
+            arch,
+            core: Arc::new(RuntimeCore::new(runtime)),
+            memory_tracker: MemoryTracker::default(),
+        })
+    }
+}
+
 #[derive(Clone)]
 pub struct Runtime {
-    inner: Rc<Runtime_>,
+    inner: Shared<Runtime_>,
 }
 impl Runtime {
     pub fn new(arch: TiArch) -> Result<Self> {
@@ -39,44 +139,194 @@ impl Runtime {
     pub fn with_device_index(arch: TiArch, device_index: u32) -> Result<Self> {
         let inner = Runtime_::new(arch, device_index)?;
         let out = Runtime {
-            inner: Rc::new(inner),
+            inner: Shared::new(inner),
         };
         Ok(out)
     }
 
+    pub fn with_preferred_archs(archs: &[TiArch]) -> Result<Self> {
+        let available_archs = available_archs()?;
+        let mut last_error = None;
+        for arch in archs {
+            if !available_archs.contains(arch) {
+                continue;
+            }
+            match Self::new(*arch) {
+                Ok(out) => return Ok(out),
+                Err(e) => last_error = Some(e),
+            }
+        }
+        let out = last_error.unwrap_or_else(|| BindingError::ArchNotAvailable {
+            preferred: archs.to_vec(),
+            available: available_archs,
+        }.into());
+        Err(out)
+    }
+    pub fn best_available() -> Result<Self> {
+        Self::with_preferred_archs(&PREFERRED_ARCHS)
+    }
+    pub fn with_capabilities(arch: TiArch, device_index: u32, capabilities: &[(TiCapability, u32)]) -> Result<Self> {
+        let out = Self::with_device_index(arch, device_index)?;
+        out.set_capabilities(capabilities)?;
+        Ok(out)
+    }
+
+    pub fn capabilities(&self) -> Result<HashMap<TiCapability, u32>> {
+        let _guard = self.enter()?;
+        let mut capability_count: u32 = 0;
+        unsafe {
+            ti_get_runtime_capabilities(self.runtime(), &mut capability_count as *mut u32, std::ptr::null_mut());
+        }
+        get_last_error()?;
+        let mut capabilities = vec![TiCapabilityLevelInfo {
+            capability: TiCapability::Reserved,
+            level: 0,
+        }; capability_count as usize];
+        unsafe {
+            ti_get_runtime_capabilities(self.runtime(), &mut capability_count as *mut u32, capabilities.as_mut_ptr());
+        }
+        get_last_error()?;
+        let out = capabilities.into_iter()
+            .take(capability_count as usize)
+            .map(|x| (x.capability, x.level))
+            .collect();
+        Ok(out)
+    }
+    pub fn has_capability(&self, capability: TiCapability) -> Result<bool> {
+        let level = self.capabilities()?
+            .get(&capability)
+            .copied()
+            .unwrap_or(0);
+        Ok(level > 0)
+    }
+    // The C-API replaces the whole capability set, so the given levels are
+    // merged into the current ones to keep the others as they are.
+    pub fn set_capabilities(&self, capabilities: &[(TiCapability, u32)]) -> Result<()> {
+        let mut merged = self.capabilities()?;
+        merged.extend(capabilities.iter().copied());
+        let capabilities = merged.into_iter()
+            .map(|(capability, level)| TiCapabilityLevelInfo { capability, level })
+            .collect::<Vec<_>>();
+        let _guard = self.enter()?;
+        unsafe {
+            ti_set_runtime_capabilities_ext(self.runtime(), capabilities.len() as u32, capabilities.as_ptr());
+        }
+        get_last_error()?;
+        Ok(())
+    }
+
+    pub fn check_thread(&self) -> Result<()> {
+        self.enter().map(|_| ())
+    }
+    // Must be held across every call into the C-API, and dropped before
+    // calling anything else that enters the runtime.
+    pub(crate) fn enter(&self) -> Result<RuntimeGuard<'_>> {
+        self.inner.core.enter()
+    }
+    pub(crate) fn release<F: FnOnce(TiRuntime) + Send + 'static>(&self, f: F) {
+        self.inner.core.release(Box::new(f))
+    }
+    pub(crate) fn core(&self) -> &Arc<RuntimeCore> {
+        &self.inner.core
+    }
+    #[cfg(feature = "sync")]
+    // Waits for a call into the runtime in progress on another thread.
+    pub fn migrate_to_current_thread(&self) {
+        self.inner.core.state.lock().unwrap()
+            .thread_affinity
+            .migrate_to_current_thread()
+    }
+
     pub fn arch(&self) -> TiArch {
         self.inner.arch
     }
     pub fn runtime(&self) -> TiRuntime {
-        self.inner.runtime
+        self.inner.core.runtime
+    }
+
+    pub fn memory_report(&self) -> MemoryReport {
+        self.inner.memory_tracker.report()
+    }
+    pub fn memory_budget(&self) -> Option<usize> {
+        self.inner.memory_tracker.budget()
+    }
+    // Allocations that would exceed the budget fail before reaching the driver.
+    pub fn set_memory_budget(&self, budget: Option<usize>) {
+        self.inner.memory_tracker.set_budget(budget);
+    }
+    pub(crate) fn memory_tracker(&self) -> &MemoryTracker {
+        &self.inner.memory_tracker
     }
 
     pub fn allocate_memory(&self) -> MemoryBuilder<'_> {
         MemoryBuilder::new(self)
     }
-    pub fn allocate_ndarray<T>(&self) -> NdArrayBuilder<'_, T> {
+    pub fn allocate_ndarray<T: Element>(&self) -> NdArrayBuilder<'_, T> {
         NdArrayBuilder::<T>::new(self)
     }
+    pub fn create_memory_pool(&self) -> MemoryPoolBuilder<'_> {
+        MemoryPoolBuilder::new(self)
+    }
 
     pub fn allocate_image(&self) -> ImageBuilder<'_> {
         ImageBuilder::new(self)
     }
+    pub fn create_sampler(&self) -> SamplerBuilder<'_> {
+        SamplerBuilder::new(self)
+    }
     pub fn allocate_texture<T>(&self) -> TextureBuilder<'_> {
         TextureBuilder::new(self)
     }
 
-    pub fn load_aot_module(&self, module_dir: &str) -> Result<AotModule> {
+    pub fn load_aot_module<P: AsRef<Path>>(&self, module_dir: P) -> Result<AotModule> {
         AotModule::load(self, module_dir)
     }
     pub fn create_aot_module(&self, tcm: &[u8]) -> Result<AotModule> {
         AotModule::new(self, tcm)
     }
+    pub fn load_scoped_aot_module<P: AsRef<Path>>(&self, module_dir: P) -> Result<scoped::AotModule<'_>> {
+        scoped::AotModule::load(self, module_dir)
+    }
+    pub fn create_scoped_aot_module(&self, tcm: &[u8]) -> Result<scoped::AotModule<'_>> {
+        scoped::AotModule::new(self, tcm)
+    }
+
+    pub fn copy_memory<'a, 'b>(&self, dst: impl Into<MemorySlice<'a>>, src: impl Into<MemorySlice<'b>>) -> Result<()> {
+        let dst = dst.into();
+        if dst.memory().runtime() != self.runtime() {
+            return Err(BindingError::RuntimeMismatch.into());
+        }
+        dst.copy_from(&src.into())
+    }
 
+    pub fn batch<R, F: FnOnce(&mut Batch<'_>) -> Result<R>>(&self, f: F) -> Result<R> {
+        let mut batch = Batch::new(self);
+        let out = f(&mut batch)?;
+        batch.submit()?;
+        Ok(out)
+    }
+    pub fn batch_and_wait<R, F: FnOnce(&mut Batch<'_>) -> Result<R>>(&self, f: F) -> Result<R> {
+        let out = self.batch(f)?;
+        self.wait()?;
+        Ok(out)
+    }
+
+    pub fn flush(&self) -> Result<()> {
+        let _guard = self.enter()?;
+        unsafe {
+            ti_flush(self.runtime());
+        }
+        get_last_error()
+            .map_err(|e| e.with_context("flush runtime", None))?;
+        Ok(())
+    }
     pub fn wait(&self) -> Result<()> {
+        let _guard = self.enter()?;
         unsafe {
             ti_wait(self.runtime());
         }
-        get_last_error()?;
+        get_last_error()
+            .map_err(|e| e.with_context("wait for runtime", None))?;
         Ok(())
     }
 
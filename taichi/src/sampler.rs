@@ -0,0 +1,108 @@
+use crate::sync::Shared;
+use taichi_sys::{TiSamplerCreateInfo, TiFilter, TiAddressMode, TiSampler, ti_create_sampler, ti_destroy_sampler, TiRuntime};
+
+use crate::{get_last_error, Result, Runtime};
+
+pub struct SamplerBuilder<'a> {
+    runtime: &'a Runtime,
+    create_info: TiSamplerCreateInfo,
+}
+impl<'a> SamplerBuilder<'a> {
+    pub fn new(runtime: &'a Runtime) -> Self {
+        let create_info = TiSamplerCreateInfo {
+            mag_filter: TiFilter::Nearest,
+            min_filter: TiFilter::Nearest,
+            address_mode: TiAddressMode::Repeat,
+            max_anisotropy: 1.0,
+        };
+        SamplerBuilder {
+            runtime,
+            create_info,
+        }
+    }
+
+    pub fn mag_filter(&mut self, mag_filter: TiFilter) -> &mut Self {
+        self.create_info.mag_filter = mag_filter;
+        self
+    }
+    pub fn min_filter(&mut self, min_filter: TiFilter) -> &mut Self {
+        self.create_info.min_filter = min_filter;
+        self
+    }
+    pub fn filter(&mut self, filter: TiFilter) -> &mut Self {
+        self.mag_filter(filter)
+            .min_filter(filter)
+    }
+    pub fn address_mode(&mut self, address_mode: TiAddressMode) -> &mut Self {
+        self.create_info.address_mode = address_mode;
+        self
+    }
+    pub fn max_anisotropy(&mut self, max_anisotropy: f32) -> &mut Self {
+        self.create_info.max_anisotropy = max_anisotropy;
+        self
+    }
+
+    pub fn build(&self) -> Result<Sampler> {
+        Sampler::new(self.runtime, &self.create_info)
+    }
+}
+
+struct Sampler_ {
+    runtime: Runtime,
+    sampler: TiSampler,
+    create_info: TiSamplerCreateInfo,
+}
+impl Sampler_ {
+    pub fn new(runtime: &Runtime, create_info: &TiSamplerCreateInfo) -> Result<Self> {
+        let _guard = runtime.enter()?;
+        let sampler = unsafe {
+            ti_create_sampler(runtime.runtime(), create_info)
+        };
+        get_last_error()?;
+        Ok(Sampler_ {
+            runtime: runtime.clone(),
+            sampler,
+            create_info: *create_info,
+        })
+    }
+}
+impl Drop for Sampler_ {
+    fn drop(&mut self) {
+        let sampler = self.sampler;
+        self.runtime.release(move |runtime| unsafe {
+            ti_destroy_sampler(runtime, sampler);
+        });
+    }
+}
+
+#[derive(Clone)]
+pub struct Sampler {
+    inner: Shared<Sampler_>,
+}
+impl Sampler {
+    pub fn new(runtime: &Runtime, create_info: &TiSamplerCreateInfo) -> Result<Self> {
+        Ok(Sampler {
+            inner: Shared::new(Sampler_::new(runtime, create_info)?),
+        })
+    }
+
+    pub fn runtime(&self) -> TiRuntime {
+        self.inner.runtime.runtime()
+    }
+    pub fn sampler(&self) -> TiSampler {
+        self.inner.sampler
+    }
+
+    pub fn mag_filter(&self) -> TiFilter {
+        self.inner.create_info.mag_filter
+    }
+    pub fn min_filter(&self) -> TiFilter {
+        self.inner.create_info.min_filter
+    }
+    pub fn address_mode(&self) -> TiAddressMode {
+        self.inner.create_info.address_mode
+    }
+    pub fn max_anisotropy(&self) -> f32 {
+        self.inner.create_info.max_anisotropy
+    }
+}
@@ -0,0 +1,47 @@
+use bytemuck::Pod;
+use taichi_sys::{TiDataType, TiScalar, TiScalarValue};
+
+mod sealed {
+    pub trait Sealed {}
+}
+pub(crate) use sealed::Sealed;
+
+pub trait Scalar: Sealed + Pod {
+    const DATA_TYPE: TiDataType;
+
+    fn to_scalar_value(self) -> TiScalarValue;
+
+    fn to_scalar(self) -> TiScalar {
+        TiScalar {
+            r#type: Self::DATA_TYPE,
+            value: self.to_scalar_value(),
+        }
+    }
+}
+
+macro_rules! impl_scalar {
+    ($ty:ty, $data_type:ident, $field:ident, |$x:ident| $cast:expr) => {
+        impl Sealed for $ty {}
+        impl Scalar for $ty {
+            const DATA_TYPE: TiDataType = TiDataType::$data_type;
+
+            fn to_scalar_value(self) -> TiScalarValue {
+                let $x = self;
+                TiScalarValue { $field: $cast }
+            }
+        }
+    };
+}
+
+impl_scalar!(f32, F32, x32, |x| x.to_bits());
+impl_scalar!(f64, F64, x64, |x| x.to_bits());
+impl_scalar!(i8, I8, x8, |x| x as u8);
+impl_scalar!(i16, I16, x16, |x| x as u16);
+impl_scalar!(i32, I32, x32, |x| x as u32);
+impl_scalar!(i64, I64, x64, |x| x as u64);
+impl_scalar!(u8, U8, x8, |x| x);
+impl_scalar!(u16, U16, x16, |x| x);
+impl_scalar!(u32, U32, x32, |x| x);
+impl_scalar!(u64, U64, x64, |x| x);
+#[cfg(feature = "half")]
+impl_scalar!(half::f16, F16, x16, |x| x.to_bits());
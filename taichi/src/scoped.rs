@@ -0,0 +1,334 @@
+// Resources that borrow the `Runtime` they are created from, so the borrow
+// checker guarantees they are all released before the runtime is dropped.
+// The reference-counted resource each of them wraps is never handed out, not
+// even by reference, so no clone of it can outlive the scoped resource. They
+// only expose raw handles and operations that don't retain the resource;
+// memory mapping is left out because mapped views give access to the shared
+// memory.
+use std::marker::PhantomData;
+use std::path::Path;
+use bytemuck::Pod;
+use taichi_sys::*;
+use crate::{
+    Result,
+    runtime::Runtime,
+    scalar::Scalar,
+    element::Element,
+    texture::Texture,
+    submission::Submission,
+    image::ImageRegion,
+    aot_metadata::{AotMetadata, ArgDescriptor, GraphDescriptor},
+};
+
+pub struct Memory<'rt> {
+    runtime: &'rt Runtime,
+    memory: crate::Memory,
+}
+impl<'rt> Memory<'rt> {
+    pub fn new(runtime: &'rt Runtime, allocate_info: &TiMemoryAllocateInfo) -> Result<Self> {
+        let memory = crate::Memory::new(runtime, allocate_info)?;
+        Ok(Memory { runtime, memory })
+    }
+
+    pub fn read<T: Pod>(&self, dst: &mut [T]) -> Result<()> {
+        self.memory.read(dst)
+    }
+    pub fn read_at<T: Pod>(&self, offset: usize, dst: &mut [T]) -> Result<()> {
+        self.memory.read_at(offset, dst)
+    }
+    pub fn to_vec<T: Pod>(&self) -> Result<Vec<T>> {
+        self.memory.to_vec()
+    }
+    pub fn write<T: Pod>(&self, src: &[T]) -> Result<()> {
+        self.memory.write(src)
+    }
+    pub fn write_at<T: Pod>(&self, offset: usize, src: &[T]) -> Result<()> {
+        self.memory.write_at(offset, src)
+    }
+    pub fn upload<T: Pod>(&self, src: &[T]) -> Result<()> {
+        self.memory.upload(src)
+    }
+    pub fn download<T: Pod>(&self, dst: &mut [T]) -> Result<()> {
+        self.memory.download(dst)
+    }
+
+    pub fn copy_from(&self, src: &Memory<'_>) -> Result<()> {
+        self.memory.as_slice().copy_from(&src.memory.as_slice())
+    }
+
+    pub fn runtime(&self) -> &'rt Runtime {
+        self.runtime
+    }
+    pub fn memory(&self) -> TiMemory {
+        self.memory.memory()
+    }
+    pub fn size(&self) -> usize {
+        self.memory.size()
+    }
+    pub fn host_read(&self) -> bool {
+        self.memory.host_read()
+    }
+    pub fn host_write(&self) -> bool {
+        self.memory.host_write()
+    }
+    pub fn usage(&self) -> TiMemoryUsageFlags {
+        self.memory.usage()
+    }
+}
+
+pub struct NdArray<'rt, T> {
+    runtime: &'rt Runtime,
+    ndarray: crate::NdArray<T>,
+}
+impl<'rt, T: Element> NdArray<'rt, T> {
+    pub(crate) fn new(runtime: &'rt Runtime, ndarray: crate::NdArray<T>) -> Self {
+        NdArray { runtime, ndarray }
+    }
+
+    pub fn read(&self, dst: &mut [T]) -> Result<()> {
+        self.ndarray.read(dst)
+    }
+    pub fn write(&self, src: &[T]) -> Result<()> {
+        self.ndarray.write(src)
+    }
+    pub fn to_vec(&self) -> Result<Vec<T>> {
+        self.ndarray.to_vec()
+    }
+    pub fn upload(&self, src: &[T]) -> Result<()> {
+        self.ndarray.upload(src)
+    }
+    pub fn download(&self, dst: &mut [T]) -> Result<()> {
+        self.ndarray.download(dst)
+    }
+
+    pub fn copy_from(&self, src: &NdArray<'_, T>) -> Result<()> {
+        self.ndarray.copy_from(&src.ndarray)
+    }
+
+    pub fn runtime(&self) -> &'rt Runtime {
+        self.runtime
+    }
+    pub fn memory(&self) -> TiMemory {
+        self.ndarray.memory().memory()
+    }
+    pub fn shape(&self) -> &[u32] {
+        self.ndarray.shape()
+    }
+    pub fn elem_shape(&self) -> &[u32] {
+        self.ndarray.elem_shape()
+    }
+    pub fn elem_count(&self) -> usize {
+        self.ndarray.elem_count()
+    }
+    pub fn scalar_count(&self) -> usize {
+        self.ndarray.scalar_count()
+    }
+    pub fn elem_type(&self) -> TiDataType {
+        self.ndarray.elem_type()
+    }
+    pub fn ndarray(&self) -> &TiNdArray {
+        self.ndarray.ndarray()
+    }
+}
+
+pub struct Image<'rt> {
+    runtime: &'rt Runtime,
+    image: crate::Image,
+}
+impl<'rt> Image<'rt> {
+    pub fn new(runtime: &'rt Runtime, allocate_info: &TiImageAllocateInfo) -> Result<Self> {
+        let image = crate::Image::new(runtime, allocate_info)?;
+        Ok(Image { runtime, image })
+    }
+
+    pub fn transition(&self, layout: TiImageLayout) -> Result<()> {
+        self.image.transition(layout)
+    }
+    pub fn track_layout(&self, layout: TiImageLayout) -> Result<()> {
+        self.image.track_layout(layout)
+    }
+    pub fn copy_from(&self, dst_region: &ImageRegion, src: &Image<'_>, src_region: &ImageRegion) -> Result<()> {
+        self.image.copy_from(dst_region, &src.image, src_region)
+    }
+
+    pub fn runtime(&self) -> &'rt Runtime {
+        self.runtime
+    }
+    pub fn image(&self) -> TiImage {
+        self.image.image()
+    }
+    pub fn dimension(&self) -> TiImageDimension {
+        self.image.dimension()
+    }
+    pub fn width(&self) -> u32 {
+        self.image.width()
+    }
+    pub fn height(&self) -> u32 {
+        self.image.height()
+    }
+    pub fn depth(&self) -> u32 {
+        self.image.depth()
+    }
+    pub fn array_layer_count(&self) -> u32 {
+        self.image.array_layer_count()
+    }
+    pub fn mip_level_count(&self) -> u32 {
+        self.image.mip_level_count()
+    }
+    pub fn format(&self) -> TiFormat {
+        self.image.format()
+    }
+    pub fn export_sharing(&self) -> bool {
+        self.image.export_sharing()
+    }
+    pub fn usage(&self) -> TiImageUsageFlags {
+        self.image.usage()
+    }
+    pub fn layout(&self) -> TiImageLayout {
+        self.image.layout()
+    }
+    pub fn mip_extent(&self, mip_level: u32) -> TiImageExtent {
+        self.image.mip_extent(mip_level)
+    }
+    pub fn region(&self, mip_level: u32) -> ImageRegion {
+        self.image.region(mip_level)
+    }
+}
+
+pub struct AotModule<'rt> {
+    runtime: &'rt Runtime,
+    aot_module: crate::AotModule,
+}
+impl<'rt> AotModule<'rt> {
+    pub fn load<P: AsRef<Path>>(runtime: &'rt Runtime, module_dir: P) -> Result<Self> {
+        let aot_module = crate::AotModule::load(runtime, module_dir)?;
+        Ok(AotModule { runtime, aot_module })
+    }
+    pub fn new(runtime: &'rt Runtime, tcm: &[u8]) -> Result<Self> {
+        let aot_module = crate::AotModule::new(runtime, tcm)?;
+        Ok(AotModule { runtime, aot_module })
+    }
+
+    pub fn get_kernel<'arg>(&self, name: &str) -> Result<Kernel<'_, 'arg>> {
+        Kernel::new(self, name)
+    }
+    pub fn get_compute_graph<'arg>(&self, name: &str) -> Result<ComputeGraph<'_, 'arg>> {
+        ComputeGraph::new(self, name)
+    }
+    pub fn get_compute_graph_unchecked<'arg>(&self, name: &str) -> Result<ComputeGraph<'_, 'arg>> {
+        ComputeGraph::new_unchecked(self, name)
+    }
+
+    pub fn metadata(&self) -> Result<Option<&AotMetadata>> {
+        self.aot_module.metadata()
+    }
+    pub fn kernel_names(&self) -> Result<Vec<&str>> {
+        self.aot_module.kernel_names()
+    }
+    pub fn graph_names(&self) -> Result<Vec<&str>> {
+        self.aot_module.graph_names()
+    }
+    pub fn graph_args(&self, name: &str) -> Result<&[ArgDescriptor]> {
+        self.aot_module.graph_args(name)
+    }
+
+    pub fn runtime(&self) -> &'rt Runtime {
+        self.runtime
+    }
+    pub fn aot_module(&self) -> TiAotModule {
+        self.aot_module.aot_module()
+    }
+}
+
+// Arguments only record raw handles, so the `'arg` lifetime keeps every bound
+// resource alive for as long as the kernel may be launched with it.
+pub struct Kernel<'m, 'arg> {
+    kernel: crate::Kernel,
+    phantom: PhantomData<(&'m (), &'arg ())>,
+}
+impl<'m, 'arg> Kernel<'m, 'arg> {
+    pub fn new(aot_module: &'m AotModule<'_>, name: &str) -> Result<Self> {
+        let kernel = aot_module.aot_module.get_kernel(name)?;
+        Ok(Kernel { kernel, phantom: PhantomData })
+    }
+
+    pub fn set_arg_i32(&mut self, index: usize, value: i32) -> Result<&mut Self> {
+        self.kernel.set_arg_i32(index, value)?;
+        Ok(self)
+    }
+    pub fn set_arg_f32(&mut self, index: usize, value: f32) -> Result<&mut Self> {
+        self.kernel.set_arg_f32(index, value)?;
+        Ok(self)
+    }
+    pub fn set_arg_scalar<T: Scalar>(&mut self, index: usize, value: T) -> Result<&mut Self> {
+        self.kernel.set_arg_scalar(index, value)?;
+        Ok(self)
+    }
+    pub fn set_arg_ndarray<T: Element>(&mut self, index: usize, value: &'arg NdArray<'_, T>) -> Result<&mut Self> {
+        self.kernel.set_arg_ndarray(index, &value.ndarray)?;
+        Ok(self)
+    }
+    pub fn set_arg_texture(&mut self, index: usize, value: &'arg Texture) -> Result<&mut Self> {
+        self.kernel.set_arg_texture(index, value)?;
+        Ok(self)
+    }
+    pub fn clear_args(&mut self) -> &mut Self {
+        self.kernel.clear_args();
+        self
+    }
+
+    pub fn kernel(&self) -> TiKernel {
+        self.kernel.kernel()
+    }
+
+    pub fn launch(&self) -> Result<Submission> {
+        self.kernel.launch()
+    }
+}
+
+pub struct ComputeGraph<'m, 'arg> {
+    compute_graph: crate::ComputeGraph,
+    phantom: PhantomData<(&'m (), &'arg ())>,
+}
+impl<'m, 'arg> ComputeGraph<'m, 'arg> {
+    pub fn new(aot_module: &'m AotModule<'_>, name: &str) -> Result<Self> {
+        let compute_graph = aot_module.aot_module.get_compute_graph(name)?;
+        Ok(ComputeGraph { compute_graph, phantom: PhantomData })
+    }
+    pub fn new_unchecked(aot_module: &'m AotModule<'_>, name: &str) -> Result<Self> {
+        let compute_graph = aot_module.aot_module.get_compute_graph_unchecked(name)?;
+        Ok(ComputeGraph { compute_graph, phantom: PhantomData })
+    }
+
+    pub fn set_arg_i32(&mut self, name: &str, value: i32) -> Result<&mut Self> {
+        self.compute_graph.set_arg_i32(name, value)?;
+        Ok(self)
+    }
+    pub fn set_arg_f32(&mut self, name: &str, value: f32) -> Result<&mut Self> {
+        self.compute_graph.set_arg_f32(name, value)?;
+        Ok(self)
+    }
+    pub fn set_arg_scalar<T: Scalar>(&mut self, name: &str, value: T) -> Result<&mut Self> {
+        self.compute_graph.set_arg_scalar(name, value)?;
+        Ok(self)
+    }
+    pub fn set_arg_ndarray<T: Element>(&mut self, name: &str, value: &'arg NdArray<'_, T>) -> Result<&mut Self> {
+        self.compute_graph.set_arg_ndarray(name, &value.ndarray)?;
+        Ok(self)
+    }
+    pub fn set_arg_texture(&mut self, name: &str, value: &'arg Texture) -> Result<&mut Self> {
+        self.compute_graph.set_arg_texture(name, value)?;
+        Ok(self)
+    }
+
+    pub fn signature(&self) -> Option<&GraphDescriptor> {
+        self.compute_graph.signature()
+    }
+    pub fn compute_graph(&self) -> TiComputeGraph {
+        self.compute_graph.compute_graph()
+    }
+
+    pub fn launch(&self) -> Result<Submission> {
+        self.compute_graph.launch()
+    }
+}
@@ -0,0 +1,157 @@
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::{Arc, Condvar, Mutex, Weak};
+use std::task::{Context, Poll, Waker};
+use taichi_sys::ti_wait;
+
+use crate::{get_last_error, Result, Runtime, runtime::RuntimeCore};
+
+#[derive(Default)]
+struct CompletionState {
+    result: Option<Result<()>>,
+    waker: Option<Waker>,
+}
+
+// Resolved by the waiter thread once its `ti_wait` returns.
+#[derive(Default)]
+pub(crate) struct Completion {
+    state: Mutex<CompletionState>,
+    cond: Condvar,
+}
+impl Completion {
+    fn complete(&self, result: Result<()>) {
+        let mut state = self.state.lock().unwrap();
+        state.result = Some(result);
+        if let Some(waker) = state.waker.take() {
+            waker.wake();
+        }
+        self.cond.notify_all();
+    }
+}
+
+#[derive(Default)]
+struct WaitQueueState {
+    waiters: Vec<Arc<Completion>>,
+    started: bool,
+    closed: bool,
+}
+
+// Submissions waiting for the runtime to drain. They are served by a single
+// waiter thread per runtime, started on first use, which calls `ti_wait`
+// while holding the runtime lock and completes every submission queued
+// before the wait started.
+#[derive(Default)]
+pub(crate) struct WaitQueue {
+    state: Mutex<WaitQueueState>,
+    cond: Condvar,
+}
+impl WaitQueue {
+    fn push(self: &Arc<Self>, core: &Arc<RuntimeCore>, completion: Arc<Completion>) {
+        let mut state = self.state.lock().unwrap();
+        state.waiters.push(completion);
+        if !state.started {
+            state.started = true;
+            let queue = self.clone();
+            let core = Arc::downgrade(core);
+            std::thread::spawn(move || queue.run(core));
+        }
+        self.cond.notify_one();
+    }
+    pub(crate) fn close(&self) {
+        self.state.lock().unwrap().closed = true;
+        self.cond.notify_one();
+    }
+
+    // The waiter thread only holds on to the runtime during a wait, so it
+    // doesn't keep the runtime alive while idle.
+    fn run(&self, core: Weak<RuntimeCore>) {
+        loop {
+            let waiters = {
+                let mut state = self.state.lock().unwrap();
+                while state.waiters.is_empty() && !state.closed {
+                    state = self.cond.wait(state).unwrap();
+                }
+                if state.closed {
+                    return;
+                }
+                std::mem::take(&mut state.waiters)
+            };
+            let Some(core) = core.upgrade() else { return };
+            let result = {
+                let _state = core.state().lock().unwrap();
+                unsafe {
+                    ti_wait(core.runtime());
+                }
+                get_last_error()
+                    .map_err(|e| e.with_context("wait for runtime", None))
+            };
+            for waiter in waiters {
+                waiter.complete(result.clone());
+            }
+        }
+    }
+}
+
+// Completion of launched work. Awaiting it queues the submission for the
+// waiter thread of the runtime so that the calling executor is never
+// blocked. Other calls into the runtime made while the waiter thread is in
+// `ti_wait` block until it returns, and resources dropped meanwhile are
+// freed afterwards. Dropping a pending submission doesn't cancel the wait.
+//
+// Without the `sync` feature a submission holds an `Rc` runtime and is not
+// `Send`, so it must be awaited on the thread that launched it.
+pub struct Submission {
+    runtime: Runtime,
+    completion: Arc<Completion>,
+    pending: bool,
+}
+impl Submission {
+    pub(crate) fn new(runtime: &Runtime) -> Self {
+        Submission {
+            runtime: runtime.clone(),
+            completion: Default::default(),
+            pending: false,
+        }
+    }
+
+    pub fn runtime(&self) -> &Runtime {
+        &self.runtime
+    }
+
+    fn start_wait(&mut self) {
+        if self.pending {
+            return;
+        }
+        self.pending = true;
+        let core = self.runtime.core();
+        core.wait_queue().push(core, self.completion.clone());
+    }
+
+    pub fn wait(self) -> Result<()> {
+        if !self.pending {
+            return self.runtime.wait();
+        }
+        let mut state = self.completion.state.lock().unwrap();
+        loop {
+            if let Some(result) = state.result.take() {
+                return result;
+            }
+            state = self.completion.cond.wait(state).unwrap();
+        }
+    }
+}
+impl Future for Submission {
+    type Output = Result<()>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        {
+            let mut state = self.completion.state.lock().unwrap();
+            if let Some(result) = state.result.take() {
+                return Poll::Ready(result);
+            }
+            state.waker = Some(cx.waker().clone());
+        }
+        self.start_wait();
+        Poll::Pending
+    }
+}
@@ -0,0 +1,43 @@
+// With the `sync` feature, wrapper handles are reference-counted with `Arc`
+// so they are `Send` and `Sync`. The C-API still requires a runtime and all
+// its resources to be driven by one thread at a time, so every runtime is
+// bound to a thread, checked under the runtime lock on each call into the
+// runtime and transferred explicitly with `Runtime::migrate_to_current_thread`.
+#[cfg(not(feature = "sync"))]
+pub(crate) type Shared<T> = std::rc::Rc<T>;
+#[cfg(feature = "sync")]
+pub(crate) type Shared<T> = std::sync::Arc<T>;
+
+#[cfg(feature = "sync")]
+pub(crate) struct ThreadAffinity(std::thread::ThreadId);
+#[cfg(feature = "sync")]
+impl ThreadAffinity {
+    pub fn new() -> Self {
+        ThreadAffinity(std::thread::current().id())
+    }
+    pub fn check(&self) -> crate::Result<()> {
+        let thread = self.0;
+        if thread != std::thread::current().id() {
+            return Err(crate::BindingError::WrongThread(format!(
+                "runtime is bound to thread {:?} but is used from thread {:?}",
+                thread, std::thread::current().id())).into());
+        }
+        Ok(())
+    }
+    pub fn migrate_to_current_thread(&mut self) {
+        self.0 = std::thread::current().id();
+    }
+}
+
+#[cfg(not(feature = "sync"))]
+pub(crate) struct ThreadAffinity;
+#[cfg(not(feature = "sync"))]
+impl ThreadAffinity {
+    pub fn new() -> Self {
+        ThreadAffinity
+    }
+    // `Rc` handles can't leave the thread they are created on.
+    pub fn check(&self) -> crate::Result<()> {
+        Ok(())
+    }
+}
@@ -0,0 +1,222 @@
+// TCM archives are zips of an AOT module directory as saved by
+// `ti.aot.Module.save`, with every file at the archive root.
+use std::collections::BTreeMap;
+use std::io::{Cursor, Read, Write};
+use std::path::{Component, Path};
+use taichi_sys::TiArch;
+use zip::{ZipArchive, ZipWriter, CompressionMethod, write::FileOptions};
+
+use crate::{BindingError, Result, aot_metadata::AotMetadata, error::ErrorSource, module_bundle::arch_from_name};
+
+fn io_error(e: std::io::Error) -> BindingError {
+    BindingError::from(e)
+}
+fn archive_error(e: zip::result::ZipError) -> BindingError {
+    match e {
+        zip::result::ZipError::Io(e) => io_error(e),
+        e => BindingError::Archive(ErrorSource(std::sync::Arc::new(e))),
+    }
+}
+
+// Entry names must stay inside the module directory when extracted.
+fn is_enclosed(name: &str) -> bool {
+    !name.is_empty() && Path::new(name).components().all(|x| matches!(x, Component::Normal(_)))
+}
+
+// File extensions of the compiled kernels each backend loads.
+fn shader_extensions(arch: TiArch) -> &'static [&'static str] {
+    match arch {
+        TiArch::Vulkan | TiArch::Opengl | TiArch::Gles => &["spv", "glsl"],
+        TiArch::Metal => &["spv", "metal", "msl"],
+        TiArch::X64 | TiArch::Arm64 | TiArch::Cuda => &["bc", "ll"],
+        _ => &[],
+    }
+}
+
+fn parse_metadata(metadata: Option<&[u8]>, graphs: Option<&[u8]>) -> Result<Option<AotMetadata>> {
+    fn utf8<'a>(name: &str, data: Option<&'a [u8]>) -> Result<Option<&'a str>> {
+        let out = data
+            .map(|x| std::str::from_utf8(x)
+                .map_err(|_| BindingError::InvalidMetadata(format!("{} is not valid utf-8", name))))
+            .transpose()?;
+        Ok(out)
+    }
+    let metadata = utf8(AotMetadata::METADATA_FILE_NAME, metadata)?;
+    let graphs = utf8(AotMetadata::GRAPHS_FILE_NAME, graphs)?;
+    if metadata.is_none() && graphs.is_none() {
+        return Ok(None);
+    }
+    AotMetadata::from_json(metadata, graphs).map(Some)
+}
+
+pub(crate) fn open_archive(tcm: &[u8]) -> Result<ZipArchive<Cursor<&[u8]>>> {
+    let out = ZipArchive::new(Cursor::new(tcm)).map_err(archive_error)?;
+    Ok(out)
+}
+// Reads the metadata entries by name without decompressing the kernels.
+pub(crate) fn read_metadata<R: Read + std::io::Seek>(archive: &mut ZipArchive<R>) -> Result<Option<AotMetadata>> {
+    let mut read = |name: &str| -> Result<Option<Vec<u8>>> {
+        let mut file = match archive.by_name(name) {
+            Ok(x) => x,
+            Err(zip::result::ZipError::FileNotFound) => return Ok(None),
+            Err(e) => return Err(archive_error(e).into()),
+        };
+        let mut data = Vec::new();
+        file.read_to_end(&mut data).map_err(io_error)?;
+        Ok(Some(data))
+    };
+    let metadata = read(AotMetadata::METADATA_FILE_NAME)?;
+    let graphs = read(AotMetadata::GRAPHS_FILE_NAME)?;
+    parse_metadata(metadata.as_deref(), graphs.as_deref())
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Tcm {
+    entries: BTreeMap<String, Vec<u8>>,
+}
+impl Tcm {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    pub fn from_reader<R: Read + std::io::Seek>(reader: R) -> Result<Self> {
+        let mut archive = ZipArchive::new(reader).map_err(archive_error)?;
+        let mut out = Tcm::new();
+        for i in 0..archive.len() {
+            let mut file = archive.by_index(i).map_err(archive_error)?;
+            if file.is_dir() {
+                continue;
+            }
+            let name = file.name().to_owned();
+            if !is_enclosed(&name) {
+                return Err(BindingError::InvalidArchive(format!(
+                    "entry {:?} escapes the module directory", name)).into());
+            }
+            // The declared size isn't trusted for preallocation.
+            let mut data = Vec::new();
+            file.read_to_end(&mut data).map_err(io_error)?;
+            out.entries.insert(name, data);
+        }
+        Ok(out)
+    }
+    pub fn from_bytes(tcm: &[u8]) -> Result<Self> {
+        Self::from_reader(Cursor::new(tcm))
+    }
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let file = std::fs::File::open(path).map_err(io_error)?;
+        Self::from_reader(std::io::BufReader::new(file))
+    }
+    // Packs every file under `module_dir`, e.g. the output directory of
+    // `ti.aot.Module.save`. Hidden files like `.gitignore` are skipped. A
+    // directory of per-arch module directories, as read by
+    // `ModuleBundle::from_arch_dirs`, is rejected; pack each arch directory
+    // into its own archive instead.
+    pub fn from_dir<P: AsRef<Path>>(module_dir: P) -> Result<Self> {
+        fn visit(out: &mut Tcm, dir: &Path, prefix: &str) -> Result<()> {
+            for entry in std::fs::read_dir(dir).map_err(io_error)? {
+                let entry = entry.map_err(io_error)?;
+                let file_name = entry.file_name();
+                let file_name = file_name.to_str()
+                    .ok_or_else(|| BindingError::InvalidPath(entry.path().display().to_string()))?;
+                if file_name.starts_with('.') {
+                    continue;
+                }
+                let name = format!("{}{}", prefix, file_name);
+                if entry.file_type().map_err(io_error)?.is_dir() {
+                    visit(out, &entry.path(), &format!("{}/", name))?;
+                } else {
+                    let data = std::fs::read(entry.path()).map_err(io_error)?;
+                    out.entries.insert(name, data);
+                }
+            }
+            Ok(())
+        }
+        let module_dir = module_dir.as_ref();
+        let mut out = Tcm::new();
+        visit(&mut out, module_dir, "")?;
+        if out.entry(AotMetadata::METADATA_FILE_NAME).is_none() {
+            let arch_dir = out.entries.keys()
+                .filter_map(|x| x.split_once('/'))
+                .find(|x| arch_from_name(x.0).is_some());
+            if let Some((arch_dir, _)) = arch_dir {
+                return Err(BindingError::InvalidArchive(format!(
+                    "{} holds modules of several archs; pack one of them, e.g. {}, instead",
+                    module_dir.display(), module_dir.join(arch_dir).display())).into());
+            }
+        }
+        Ok(out)
+    }
+
+    pub fn entry_names(&self) -> Vec<&str> {
+        self.entries.keys().map(String::as_str).collect()
+    }
+    pub fn entry(&self, name: &str) -> Option<&[u8]> {
+        self.entries.get(name).map(Vec::as_slice)
+    }
+    pub fn insert(&mut self, name: &str, data: Vec<u8>) -> Result<&mut Self> {
+        if !is_enclosed(name) {
+            return Err(BindingError::InvalidArchive(format!(
+                "entry {:?} escapes the module directory", name)).into());
+        }
+        self.entries.insert(name.to_owned(), data);
+        Ok(self)
+    }
+    pub fn remove(&mut self, name: &str) -> Option<Vec<u8>> {
+        self.entries.remove(name)
+    }
+
+    pub fn metadata(&self) -> Result<Option<AotMetadata>> {
+        parse_metadata(self.entry(AotMetadata::METADATA_FILE_NAME), self.entry(AotMetadata::GRAPHS_FILE_NAME))
+    }
+    // Checks that the archive has module metadata and at least one compiled
+    // kernel the backend of `arch` can load.
+    pub fn validate(&self, arch: TiArch) -> Result<()> {
+        if self.entry(AotMetadata::METADATA_FILE_NAME).is_none() {
+            return Err(BindingError::InvalidArchive(format!(
+                "missing {}", AotMetadata::METADATA_FILE_NAME)).into());
+        }
+        self.metadata()?;
+        let extensions = shader_extensions(arch);
+        let has_shader = self.entries.keys()
+            .filter_map(|x| Path::new(x).extension().and_then(|x| x.to_str()))
+            .any(|x| extensions.contains(&x));
+        if !has_shader {
+            return Err(BindingError::InvalidArchive(format!(
+                "no compiled kernels for {:?}; expected files ending with {:?}", arch, extensions)).into());
+        }
+        Ok(())
+    }
+
+    pub fn write<W: Write + std::io::Seek>(&self, writer: W) -> Result<()> {
+        let mut zip = ZipWriter::new(writer);
+        let options = FileOptions::default()
+            .compression_method(CompressionMethod::Deflated);
+        for (name, data) in self.entries.iter() {
+            zip.start_file(name.as_str(), options).map_err(archive_error)?;
+            zip.write_all(data).map_err(io_error)?;
+        }
+        let mut writer = zip.finish().map_err(archive_error)?;
+        writer.flush().map_err(io_error)?;
+        Ok(())
+    }
+    pub fn to_bytes(&self) -> Result<Vec<u8>> {
+        let mut out = Cursor::new(Vec::new());
+        self.write(&mut out)?;
+        Ok(out.into_inner())
+    }
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        let file = std::fs::File::create(path).map_err(io_error)?;
+        self.write(std::io::BufWriter::new(file))
+    }
+    pub fn extract_to<P: AsRef<Path>>(&self, module_dir: P) -> Result<()> {
+        let module_dir = module_dir.as_ref();
+        for (name, data) in self.entries.iter() {
+            let path = module_dir.join(name);
+            if let Some(parent) = path.parent() {
+                std::fs::create_dir_all(parent).map_err(io_error)?;
+            }
+            std::fs::write(path, data).map_err(io_error)?;
+        }
+        Ok(())
+    }
+}
@@ -1,11 +1,36 @@
 use taichi_sys::*;
 use crate::*;
 
+fn block_on<F: std::future::Future>(future: F) -> F::Output {
+    struct ThreadWaker(std::thread::Thread);
+    impl std::task::Wake for ThreadWaker {
+        fn wake(self: std::sync::Arc<Self>) {
+            self.0.unpark();
+        }
+    }
+
+    let mut future = std::pin::pin!(future);
+    let waker = std::sync::Arc::new(ThreadWaker(std::thread::current())).into();
+    let mut cx = std::task::Context::from_waker(&waker);
+    loop {
+        match future.as_mut().poll(&mut cx) {
+            std::task::Poll::Ready(x) => return x,
+            std::task::Poll::Pending => std::thread::park(),
+        }
+    }
+}
+
 fn get_platform_arch() -> TiArch {
-    if cfg!(target_os = "macos") {
+    let archs = available_archs().unwrap();
+    let preferred_arch = if cfg!(target_os = "macos") {
         TiArch::Metal
     } else {
         TiArch::Vulkan
+    };
+    if archs.contains(&preferred_arch) {
+        preferred_arch
+    } else {
+        TiArch::X64
     }
 }
 
@@ -75,3 +100,886 @@ fn test_launch_compute_graph() {
     ndarray.read(&mut actual_data).unwrap();
     assert_eq!(expect_data, actual_data);
 }
+#[test]
+fn test_launch_kernel() {
+    let runtime = Runtime::new(get_platform_arch()).unwrap();
+    let ndarray = runtime.allocate_ndarray::<i32>()
+        .shape([16, 16])
+        .host_read(true)
+        .build()
+        .unwrap();
+    let module = runtime.load_aot_module("../assets/chess_board/module").unwrap();
+    let mut chess_board = module.get_kernel("chess_board").unwrap();
+    chess_board.set_arg_ndarray(0, &ndarray).unwrap();
+    chess_board.launch().unwrap();
+    runtime.wait().unwrap();
+
+    let mut expect_data = Vec::new();
+    for i in 0..16 {
+        for j in 0..16 {
+            let x = (j * (16 + 1) + i) % 2;
+            expect_data.push(x);
+        }
+    }
+    let mut actual_data = [0; 16 * 16].to_vec();
+    ndarray.read(&mut actual_data).unwrap();
+    assert_eq!(expect_data, actual_data);
+}
+#[test]
+fn test_create_texture_with_sampler() {
+    let runtime = Runtime::new(get_platform_arch()).unwrap();
+    let sampler = runtime.create_sampler()
+        .filter(TiFilter::Linear)
+        .address_mode(TiAddressMode::ClampToEdge)
+        .build()
+        .unwrap();
+    let texture = runtime.allocate_texture::<f32>()
+        .width(16)
+        .height(16)
+        .format(TiFormat::Rgba32F)
+        .sampler(&sampler)
+        .build()
+        .unwrap();
+    assert_eq!(texture.texture().sampler, sampler.sampler());
+    assert_eq!(texture.sampler().unwrap().mag_filter(), TiFilter::Linear);
+}
+#[test]
+fn test_scalar_packing() {
+    let x = 1.5f64.to_scalar();
+    assert_eq!(x.r#type, TiDataType::F64);
+    assert_eq!(unsafe { x.value.x64 }, 1.5f64.to_bits());
+    let x = (-1i16).to_scalar();
+    assert_eq!(x.r#type, TiDataType::I16);
+    assert_eq!(unsafe { x.value.x16 }, 0xffff);
+    let x = u64::MAX.to_scalar();
+    assert_eq!(x.r#type, TiDataType::U64);
+    assert_eq!(unsafe { x.value.x64 }, u64::MAX);
+}
+#[test]
+fn test_element_type() {
+    assert_eq!(f32::ELEM_TYPE, TiDataType::F32);
+    assert_eq!(i64::ELEM_TYPE, TiDataType::I64);
+    assert_eq!(u8::ELEM_TYPE, TiDataType::U8);
+}
+#[cfg(feature = "u1")]
+#[test]
+fn test_u1_element() {
+    assert_eq!(U1::ELEM_TYPE, TiDataType::U1);
+    assert_eq!(std::mem::size_of::<U1>(), 1);
+    assert_eq!(U1::from(true), U1(1));
+    assert!(!bool::from(U1::default()));
+    assert!(bool::from(U1(2)));
+}
+#[test]
+fn test_host_accessible_ndarray_to_vec() {
+    let runtime = Runtime::new(get_platform_arch()).unwrap();
+    let ndarray = runtime.allocate_ndarray::<f32>()
+        .shape([4, 4])
+        .host_access(true)
+        .build()
+        .unwrap();
+    assert_eq!(ndarray.elem_type(), TiDataType::F32);
+
+    let input = (0..16).map(|x| x as f32).collect::<Vec<f32>>();
+    ndarray.write(&input).unwrap();
+    assert_eq!(ndarray.to_vec().unwrap(), input);
+}
+#[test]
+fn test_vector_matrix_element_shape() {
+    assert_eq!(<[f32; 3]>::ELEM_TYPE, TiDataType::F32);
+    assert_eq!(<[f32; 3]>::elem_shape(), vec![3]);
+    assert_eq!(<[[i32; 4]; 3]>::ELEM_TYPE, TiDataType::I32);
+    assert_eq!(<[[i32; 4]; 3]>::elem_shape(), vec![3, 4]);
+}
+#[test]
+fn test_host_accessible_vector_ndarray_read_write() {
+    let runtime = Runtime::new(get_platform_arch()).unwrap();
+    let ndarray = runtime.allocate_ndarray::<[f32; 3]>()
+        .shape([8])
+        .host_access(true)
+        .build()
+        .unwrap();
+    assert_eq!(ndarray.elem_shape(), &[3]);
+    assert_eq!(ndarray.elem_type(), TiDataType::F32);
+    assert_eq!(ndarray.scalar_count(), 8 * 3);
+
+    let input = (0..8).map(|x| [x as f32, 0.0, 1.0]).collect::<Vec<_>>();
+    ndarray.write(&input).unwrap();
+    assert_eq!(ndarray.to_vec().unwrap(), input);
+}
+#[test]
+fn test_memory_slice_out_of_range() {
+    let runtime = Runtime::new(get_platform_arch()).unwrap();
+    let memory = runtime.allocate_memory()
+        .size(64)
+        .build()
+        .unwrap();
+    memory.slice(16, 48).unwrap();
+    assert_eq!(memory.slice(16, 64).err().unwrap().code(), TiError::ArgumentOutOfRange);
+    assert_eq!(memory.slice(usize::MAX, 1).err().unwrap().code(), TiError::ArgumentOutOfRange);
+}
+#[test]
+fn test_memory_slice_overlapping_copy() {
+    let runtime = Runtime::new(get_platform_arch()).unwrap();
+    let memory = runtime.allocate_memory()
+        .size(64)
+        .build()
+        .unwrap();
+    let err = memory.slice(0, 32).unwrap().copy_from(&memory.slice(16, 32).unwrap()).err().unwrap();
+    assert_eq!(err.binding_error(), Some(&BindingError::OverlappingCopy { dst: (0, 32), src: (16, 48) }));
+    assert_eq!(err.code(), TiError::InvalidArgument);
+    assert_eq!(err.to_string(), "memory ranges [0, 32) and [16, 48) of the same memory overlap");
+    let err = memory.as_slice().copy_from(&memory.as_slice()).err().unwrap();
+    assert_eq!(err.binding_error(), Some(&BindingError::OverlappingCopy { dst: (0, 64), src: (0, 64) }));
+    memory.slice(0, 32).unwrap().copy_from(&memory.slice(32, 32).unwrap()).unwrap();
+}
+#[test]
+fn test_copy_ndarray_device_to_device() {
+    let runtime = Runtime::new(get_platform_arch()).unwrap();
+    let src = runtime.allocate_ndarray::<u32>()
+        .shape([128])
+        .host_write(true)
+        .build()
+        .unwrap();
+    let dst = runtime.allocate_ndarray::<u32>()
+        .shape([128])
+        .host_read(true)
+        .build()
+        .unwrap();
+    let input = (0..128).collect::<Vec<u32>>();
+    src.write(&input).unwrap();
+    dst.copy_from(&src).unwrap();
+    runtime.wait().unwrap();
+    assert_eq!(dst.to_vec().unwrap(), input);
+
+    let mismatched = runtime.allocate_ndarray::<u32>()
+        .shape([64, 2])
+        .build()
+        .unwrap();
+    assert_eq!(mismatched.copy_from(&src).err().unwrap().code(), TiError::InvalidArgument);
+}
+#[test]
+fn test_device_only_memory_upload_download() {
+    let runtime = Runtime::new(get_platform_arch()).unwrap();
+    let memory = runtime.allocate_memory()
+        .size(128 * std::mem::size_of::<u32>())
+        .build()
+        .unwrap();
+
+    let input = (0..128).collect::<Vec<u32>>();
+    memory.upload(&input).unwrap();
+    let mut output = [0u32; 128].to_vec();
+    memory.download(&mut output).unwrap();
+    assert_eq!(input, output);
+    // Staging memory is released once a transfer is done.
+    assert_eq!(runtime.memory_report().memory.count, 1);
+
+    let mut output = [0u32; 64].to_vec();
+    assert_eq!(memory.download(&mut output).err().unwrap().code(), TiError::ArgumentOutOfRange);
+}
+#[test]
+fn test_device_only_ndarray_upload_download() {
+    let runtime = Runtime::new(get_platform_arch()).unwrap();
+    let ndarray = runtime.allocate_ndarray::<[f32; 4]>()
+        .shape([16, 16])
+        .build()
+        .unwrap();
+
+    let input = (0..256).map(|x| [x as f32; 4]).collect::<Vec<_>>();
+    ndarray.upload(&input).unwrap();
+    let mut output = [[0.0f32; 4]; 256].to_vec();
+    ndarray.download(&mut output).unwrap();
+    assert_eq!(input, output);
+}
+#[test]
+fn test_copy_image_device_to_device() {
+    let runtime = Runtime::new(get_platform_arch()).unwrap();
+    let src = runtime.allocate_image()
+        .width(64)
+        .height(64)
+        .mip_level_count(2)
+        .build()
+        .unwrap();
+    let dst = runtime.allocate_image()
+        .width(32)
+        .height(32)
+        .build()
+        .unwrap();
+    dst.copy_from(&dst.region(0), &src, &src.region(1)).unwrap();
+    runtime.wait().unwrap();
+
+    let err = dst.copy_from(&dst.region(0), &src, &src.region(0)).err().unwrap();
+    assert_eq!(err.code(), TiError::InvalidArgument);
+    let err = dst.copy_from(&dst.region(0), &src, &src.region(2)).err().unwrap();
+    assert_eq!(err.code(), TiError::ArgumentOutOfRange);
+}
+#[test]
+fn test_image_layout_transition() {
+    let runtime = Runtime::new(get_platform_arch()).unwrap();
+    let image = runtime.allocate_image()
+        .width(16)
+        .height(16)
+        .build()
+        .unwrap();
+    assert_eq!(image.layout(), TiImageLayout::Undefined);
+    image.transition(TiImageLayout::ShaderReadWrite).unwrap();
+    assert_eq!(image.layout(), TiImageLayout::ShaderReadWrite);
+    image.track_layout(TiImageLayout::ShaderRead).unwrap();
+    assert_eq!(image.layout(), TiImageLayout::ShaderRead);
+}
+#[test]
+fn test_runtime_capabilities() {
+    let runtime = Runtime::new(TiArch::X64).unwrap();
+    let capabilities = runtime.capabilities().unwrap();
+    assert!(!capabilities.contains_key(&TiCapability::Reserved));
+
+    runtime.set_capabilities(&[(TiCapability::SpirvVersion, 0x10300)]).unwrap();
+    assert_eq!(runtime.capabilities().unwrap()[&TiCapability::SpirvVersion], 0x10300);
+    assert!(runtime.has_capability(TiCapability::SpirvVersion).unwrap());
+
+    // Setting another capability merges it into the current set.
+    let mut before = runtime.capabilities().unwrap();
+    runtime.set_capabilities(&[(TiCapability::SpirvHasInt8, 1)]).unwrap();
+    let mut after = runtime.capabilities().unwrap();
+    assert_eq!(after[&TiCapability::SpirvVersion], 0x10300);
+    assert_eq!(after.remove(&TiCapability::SpirvHasInt8), Some(1));
+    before.remove(&TiCapability::SpirvHasInt8);
+    assert_eq!(after, before);
+}
+#[test]
+fn test_runtime_with_capabilities() {
+    let runtime = Runtime::with_capabilities(TiArch::X64, 0, &[
+        (TiCapability::SpirvHasInt64, 1),
+        (TiCapability::SpirvHasFloat64, 1),
+    ]).unwrap();
+    assert!(runtime.has_capability(TiCapability::SpirvHasInt64).unwrap());
+    assert!(runtime.has_capability(TiCapability::SpirvHasFloat64).unwrap());
+
+    let mut expected = runtime.capabilities().unwrap();
+    runtime.set_capabilities(&[(TiCapability::SpirvHasFloat64, 0)]).unwrap();
+    assert!(!runtime.has_capability(TiCapability::SpirvHasFloat64).unwrap());
+    assert!(runtime.has_capability(TiCapability::SpirvHasInt64).unwrap());
+
+    // Levels that weren't overridden are kept.
+    expected.remove(&TiCapability::SpirvHasFloat64);
+    let mut actual = runtime.capabilities().unwrap();
+    actual.remove(&TiCapability::SpirvHasFloat64);
+    assert_eq!(actual, expected);
+}
+#[test]
+fn test_available_archs() {
+    let archs = available_archs().unwrap();
+    assert!(!archs.contains(&TiArch::Reserved));
+}
+#[test]
+fn test_create_best_available_runtime() {
+    let runtime = Runtime::best_available().unwrap();
+    assert!(available_archs().unwrap().contains(&runtime.arch()));
+}
+#[test]
+fn test_create_runtime_with_preferred_archs() {
+    let runtime = Runtime::with_preferred_archs(&[TiArch::Reserved, TiArch::X64]).unwrap();
+    assert_eq!(runtime.arch(), TiArch::X64);
+    let err = Runtime::with_preferred_archs(&[TiArch::Reserved]).err().unwrap();
+    assert_eq!(err.code(), TiError::NotSupported);
+}
+#[test]
+fn test_batch_launch_and_copy() {
+    let runtime = Runtime::new(get_platform_arch()).unwrap();
+    let ndarray = runtime.allocate_ndarray::<i32>()
+        .shape([16, 16])
+        .build()
+        .unwrap();
+    let readback = runtime.allocate_ndarray::<i32>()
+        .shape([16, 16])
+        .host_read(true)
+        .build()
+        .unwrap();
+    let module = runtime.load_aot_module("../assets/chess_board/module").unwrap();
+    let mut g_run = module.get_compute_graph("g_run").unwrap();
+    g_run.set_arg_ndarray("arr", &ndarray).unwrap();
+
+    let command_count = runtime.batch_and_wait(|b| {
+        b.launch_compute_graph(&g_run)?
+            .copy_memory(&readback.memory().as_slice(), &ndarray.memory().as_slice())?;
+        Ok(b.command_count())
+    }).unwrap();
+    assert_eq!(command_count, 2);
+
+    let actual_data = readback.to_vec().unwrap();
+    assert_eq!(actual_data[0], 0);
+    assert_eq!(actual_data[1], 1);
+}
+#[test]
+fn test_batch_records_commands() {
+    let runtime = Runtime::new(get_platform_arch()).unwrap();
+    let memory = runtime.allocate_memory()
+        .size(64)
+        .build()
+        .unwrap();
+    let err = runtime.batch(|b| {
+        let staging = runtime.allocate_memory()
+            .size(32)
+            .build()?;
+        b.copy_memory(&memory.slice(0, 32)?, &staging.as_slice())?;
+        drop(staging);
+        // Recorded commands keep their resources alive.
+        assert_eq!(runtime.memory_report().memory.count, 2);
+        b.copy_memory(&memory.slice(0, 32)?, &memory.slice(16, 32)?)?;
+        Ok(())
+    }).err().unwrap();
+    assert!(matches!(err.binding_error(), Some(BindingError::OverlappingCopy { .. })));
+    assert_eq!(runtime.memory_report().memory.count, 1);
+}
+#[test]
+fn test_await_compute_graph_submission() {
+    let runtime = Runtime::new(get_platform_arch()).unwrap();
+    let ndarray = runtime.allocate_ndarray::<i32>()
+        .shape([16, 16])
+        .host_read(true)
+        .build()
+        .unwrap();
+    let module = runtime.load_aot_module("../assets/chess_board/module").unwrap();
+    let mut g_run = module.get_compute_graph("g_run").unwrap();
+    g_run.set_arg_ndarray("arr", &ndarray).unwrap();
+    let submission = g_run.launch().unwrap();
+    block_on(submission).unwrap();
+
+    let actual_data = ndarray.to_vec().unwrap();
+    assert_eq!(actual_data[0], 0);
+    assert_eq!(actual_data[1], 1);
+}
+#[test]
+fn test_submission_wait_queues_runtime_calls() {
+    let runtime = Runtime::new(get_platform_arch()).unwrap();
+    block_on(Submission::new(&runtime)).unwrap();
+    Submission::new(&runtime).wait().unwrap();
+    // Later submissions are served by the same waiter thread.
+    block_on(Submission::new(&runtime)).unwrap();
+
+    // A submission dropped while pending doesn't cancel its wait.
+    let mut submission = Box::pin(Submission::new(&runtime));
+    let mut cx = std::task::Context::from_waker(std::task::Waker::noop());
+    let _ = std::future::Future::poll(submission.as_mut(), &mut cx);
+    drop(submission);
+    runtime.allocate_memory().size(64).build().unwrap();
+
+    // Hold the runtime lock like the waiter thread does during `ti_wait`.
+    let memory = runtime.allocate_memory().size(64).build().unwrap();
+    let core = runtime.core().clone();
+    let (locked_tx, locked_rx) = std::sync::mpsc::channel();
+    let (release_tx, release_rx) = std::sync::mpsc::channel::<()>();
+    let waiter = std::thread::spawn(move || {
+        let _state = core.state().lock().unwrap();
+        locked_tx.send(()).unwrap();
+        release_rx.recv().unwrap();
+    });
+    locked_rx.recv().unwrap();
+    drop(memory);
+    assert_eq!(runtime.core().deferred_count(), 1);
+    release_tx.send(()).unwrap();
+    // Calls made meanwhile wait for the lock instead of failing.
+    runtime.flush().unwrap();
+    assert_eq!(runtime.core().deferred_count(), 0);
+    waiter.join().unwrap();
+}
+#[cfg(feature = "sync")]
+#[test]
+fn test_sync_handles_are_send() {
+    fn assert_send_sync<T: Send + Sync>() {}
+    assert_send_sync::<Runtime>();
+    assert_send_sync::<Memory>();
+    assert_send_sync::<NdArray<f32>>();
+    assert_send_sync::<Image>();
+    assert_send_sync::<Texture>();
+    assert_send_sync::<AotModule>();
+    assert_send_sync::<ComputeGraph>();
+    assert_send_sync::<Kernel>();
+}
+#[cfg(feature = "sync")]
+#[test]
+fn test_sync_runtime_thread_affinity() {
+    let runtime = Runtime::new(get_platform_arch()).unwrap();
+    let ndarray = runtime.allocate_ndarray::<u32>()
+        .shape([128])
+        .host_access(true)
+        .build()
+        .unwrap();
+
+    let (runtime, ndarray) = std::thread::spawn(move || {
+        let err = ndarray.write(&[0; 128]).err().unwrap();
+        assert_eq!(err.code(), TiError::InvalidState);
+        runtime.migrate_to_current_thread();
+        ndarray.write(&[1; 128]).unwrap();
+        (runtime, ndarray)
+    }).join().unwrap();
+
+    assert_eq!(runtime.wait().err().unwrap().code(), TiError::InvalidState);
+    runtime.migrate_to_current_thread();
+    assert_eq!(ndarray.to_vec().unwrap(), [1; 128]);
+}
+#[cfg(feature = "sync")]
+#[test]
+fn test_sync_wrong_thread_drop_is_deferred() {
+    let runtime = Runtime::new(get_platform_arch()).unwrap();
+    let memory = runtime.allocate_memory()
+        .size(64)
+        .build()
+        .unwrap();
+    let sampler = runtime.create_sampler()
+        .build()
+        .unwrap();
+    std::thread::spawn(move || {
+        drop(memory);
+        drop(sampler);
+    }).join().unwrap();
+    assert_eq!(runtime.memory_report().memory.count, 0);
+    assert_eq!(runtime.core().deferred_count(), 2);
+
+    runtime.flush().unwrap();
+    assert_eq!(runtime.core().deferred_count(), 0);
+}
+#[test]
+fn test_scoped_resources() {
+    let runtime = Runtime::new(get_platform_arch()).unwrap();
+    let ndarray = runtime.allocate_ndarray::<i32>()
+        .shape([16, 16])
+        .host_read(true)
+        .build_scoped()
+        .unwrap();
+    let module = runtime.load_scoped_aot_module("../assets/chess_board/module").unwrap();
+    let mut g_run = module.get_compute_graph("g_run").unwrap();
+    g_run.set_arg_ndarray("arr", &ndarray).unwrap();
+    g_run.launch().unwrap().wait().unwrap();
+    let mut chess_board = module.get_kernel("chess_board").unwrap();
+    chess_board.set_arg_ndarray(0, &ndarray).unwrap();
+    block_on(chess_board.launch().unwrap()).unwrap();
+
+    let mut actual_data = [0; 16 * 16].to_vec();
+    ndarray.read(&mut actual_data).unwrap();
+    assert_eq!(actual_data[0], 0);
+    assert_eq!(actual_data[1], 1);
+}
+#[test]
+fn test_binding_error_context() {
+    let err = Error::from(BindingError::NulInName("g\0run".to_owned()))
+        .with_context("get compute graph", Some("g\0run"));
+    assert_eq!(err.code(), TiError::InvalidArgument);
+    assert_eq!(err.binding_error(), Some(&BindingError::NulInName("g\0run".to_owned())));
+    assert_eq!(err.to_string(), "failed to get compute graph \"g\\0run\"");
+    let source = std::error::Error::source(&err).unwrap();
+    assert_eq!(source.to_string(), "\"g\\0run\" contains a nul character");
+    assert_eq!(err.message(), "failed to get compute graph \"g\\0run\": \"g\\0run\" contains a nul character");
+
+    let io = std::io::Error::new(std::io::ErrorKind::NotFound, "no such file");
+    let err = Error::from(BindingError::from(io))
+        .with_context("open tcm", Some("module.tcm"));
+    assert!(matches!(err.binding_error(), Some(BindingError::Io(_))));
+    assert_eq!(err.message(), "failed to open tcm \"module.tcm\": io error: no such file");
+
+    let err = Error::new(TiError::NameNotFound, "g_run".to_owned());
+    assert_eq!(err.binding_error(), None);
+    assert_eq!(err.message(), "g_run");
+}
+#[test]
+fn test_ndarray_dim_overflow() {
+    let runtime = Runtime::new(get_platform_arch()).unwrap();
+    let err = runtime.allocate_ndarray::<f32>()
+        .shape([1; 17])
+        .build()
+        .err()
+        .unwrap();
+    assert_eq!(err.binding_error(), Some(&BindingError::DimOverflow { dim_count: 17 }));
+
+    let err = runtime.allocate_ndarray::<f32>()
+        .shape([u32::MAX; 4])
+        .build()
+        .err()
+        .unwrap();
+    assert_eq!(err.code(), TiError::ArgumentOutOfRange);
+}
+#[test]
+fn test_memory_read_write_validation() {
+    let runtime = Runtime::new(get_platform_arch()).unwrap();
+    let memory = runtime.allocate_memory()
+        .size(16)
+        .host_read(true)
+        .host_write(true)
+        .build()
+        .unwrap();
+
+    let mut short = [0u32; 3];
+    let err = memory.read(&mut short).err().unwrap();
+    assert_eq!(err.code(), TiError::ArgumentOutOfRange);
+    assert_eq!(err.binding_error(), Some(&BindingError::SizeMismatch { what: "read destination", expected: 16, actual: 12 }));
+    let err = memory.write(&[0u8; 17]).err().unwrap();
+    assert_eq!(err.code(), TiError::ArgumentOutOfRange);
+    let err = memory.read(&mut [[0u8; 3]; 5]).err().unwrap();
+    assert_eq!(err.binding_error(), Some(&BindingError::Unaligned { what: "memory size", value: 16, granularity: 3 }));
+
+    let err = memory.read_at(12, &mut [0u32; 2]).err().unwrap();
+    assert_eq!(err.code(), TiError::ArgumentOutOfRange);
+    let err = memory.write_at(2, &[0u32; 1]).err().unwrap();
+    assert_eq!(err.binding_error(), Some(&BindingError::Unaligned { what: "offset", value: 2, granularity: 4 }));
+    let err = memory.read_at(usize::MAX - 3, &mut [0u32; 1]).err().unwrap();
+    assert_eq!(err.code(), TiError::ArgumentOutOfRange);
+}
+#[test]
+fn test_mapped_memory_slices() {
+    let runtime = Runtime::new(get_platform_arch()).unwrap();
+    let memory = runtime.allocate_memory()
+        .size(128 * std::mem::size_of::<u32>())
+        .host_read(true)
+        .host_write(true)
+        .build()
+        .unwrap();
+    {
+        let mut mapped = memory.map_mut::<u32>().unwrap();
+        for (i, x) in mapped.iter_mut().enumerate() {
+            *x = i as u32;
+        }
+    }
+    let mapped = memory.map::<u32>().unwrap();
+    assert_eq!(mapped.len(), 128);
+    assert!(mapped.iter().enumerate().all(|(i, x)| *x == i as u32));
+}
+#[test]
+fn test_mapped_memory_access_flags() {
+    let runtime = Runtime::new(get_platform_arch()).unwrap();
+    let memory = runtime.allocate_memory()
+        .size(16)
+        .host_write(true)
+        .build()
+        .unwrap();
+    let err = memory.map::<u32>().err().unwrap();
+    assert_eq!(err.binding_error(), Some(&BindingError::NotHostReadable));
+    let err = memory.map_mut::<[u8; 3]>().err().unwrap();
+    assert_eq!(err.code(), TiError::ArgumentOutOfRange);
+
+    let mapped = memory.map_mut::<u32>().unwrap();
+    let err = memory.map_mut::<u32>().err().unwrap();
+    assert_eq!(err.binding_error(), Some(&BindingError::AlreadyMapped));
+    drop(mapped);
+    assert!(memory.map_mut::<u32>().is_ok());
+}
+#[test]
+fn test_memory_pool_sub_allocation() {
+    let runtime = Runtime::new(get_platform_arch()).unwrap();
+    let pool = runtime.create_memory_pool()
+        .block_size(1024)
+        .alignment(256)
+        .build()
+        .unwrap();
+
+    let a = pool.allocate(100).unwrap();
+    let b = pool.allocate(300).unwrap();
+    assert_eq!((a.offset(), a.size()), (0, 100));
+    assert_eq!((b.offset(), b.size()), (256, 300));
+    assert_eq!(b.slice().offset(), 256);
+    let stats = pool.stats();
+    assert_eq!((stats.block_count, stats.reserved_size), (1, 1024));
+    assert_eq!((stats.used_size, stats.allocation_count), (256 + 512, 2));
+
+    let big = pool.allocate(4096).unwrap();
+    assert_eq!((big.offset(), big.memory().size()), (0, 4096));
+    assert_eq!(pool.stats().block_count, 2);
+    drop(big);
+    pool.trim();
+    assert_eq!(pool.stats().block_count, 1);
+
+    drop(a);
+    let c = pool.allocate(200).unwrap();
+    assert_eq!(c.offset(), 0);
+    drop(b);
+    drop(c);
+    let stats = pool.stats();
+    assert_eq!((stats.used_size, stats.allocation_count, stats.peak_used_size), (0, 0, 256 + 512 + 4096));
+    assert_eq!(pool.allocate(0).err().unwrap().code(), TiError::ArgumentOutOfRange);
+}
+#[test]
+fn test_memory_pool_allocation_into_ndarray() {
+    let runtime = Runtime::new(get_platform_arch()).unwrap();
+    let pool = runtime.create_memory_pool()
+        .block_size(1024)
+        .alignment(256)
+        .build()
+        .unwrap();
+
+    let err = pool.allocate(256).unwrap().into_ndarray::<f32, _>([64]).err().unwrap();
+    assert_eq!(err.code(), TiError::ArgumentOutOfRange);
+    let err = pool.allocate(2048).unwrap().into_ndarray::<f32, _>([256]).err().unwrap();
+    assert_eq!(err.binding_error(), Some(&BindingError::SizeMismatch { what: "ndarray memory", expected: 1024, actual: 2048 }));
+
+    let allocation = pool.allocate(2048).unwrap();
+    let memory = allocation.memory().memory();
+    let ndarray = allocation.into_ndarray::<[f32; 4], _>([8, 16]).unwrap();
+    assert_eq!(ndarray.memory().memory(), memory);
+    assert_eq!(ndarray.shape(), &[8, 16]);
+    assert_eq!(ndarray.elem_shape(), &[4]);
+    pool.trim();
+    let stats = pool.stats();
+    assert_eq!((stats.block_count, stats.reserved_size), (0, 0));
+    assert_eq!((stats.used_size, stats.allocation_count), (0, 0));
+}
+#[test]
+fn test_runtime_memory_report() {
+    let runtime = Runtime::new(get_platform_arch()).unwrap();
+    let memory = runtime.allocate_memory()
+        .size(1024)
+        .build()
+        .unwrap();
+    let image = runtime.allocate_image()
+        .dimension(TiImageDimension::D2D)
+        .width(16)
+        .height(16)
+        .format(TiFormat::Rgba8)
+        .build()
+        .unwrap();
+    let report = runtime.memory_report();
+    assert_eq!(report.memory, AllocationStats { count: 1, size: 1024 });
+    assert_eq!(report.memory_by_usage[&TiMemoryUsageFlags::STORAGE_BIT].size, 1024);
+    assert_eq!(report.image, AllocationStats { count: 1, size: 16 * 16 * 4 });
+    assert_eq!(report.image_by_format[&TiFormat::Rgba8].count, 1);
+
+    runtime.set_memory_budget(Some(2048));
+    let err = runtime.allocate_memory()
+        .size(1024)
+        .build()
+        .err()
+        .unwrap();
+    assert_eq!(err.code(), TiError::OutOfMemory);
+
+    drop(memory);
+    drop(image);
+    let report = runtime.memory_report();
+    assert_eq!(report.total_size(), 0);
+    assert!(report.memory_by_usage.is_empty());
+}
+// `assets/chess_board/metadata` holds the `metadata.json` and `graphs.json`
+// of the chess board module in the layout Taichi 1.6 saves them in. They are
+// written after Taichi's serializers rather than copied from a module saved
+// by `chess_board.py`; replace them with the saved files when regenerating.
+#[test]
+fn test_parse_aot_metadata() {
+    let metadata = AotMetadata::load(std::path::Path::new("../assets/chess_board/metadata")).unwrap().unwrap();
+    assert_eq!(metadata.kernel_names(), ["chess_board"]);
+    assert_eq!(metadata.graph_names(), ["g_run"]);
+    let g_run = metadata.graph("g_run").unwrap();
+    assert_eq!(g_run.args, [ArgDescriptor {
+        name: "arr".to_owned(),
+        kind: ArgKind::Ndarray,
+        dtype: TiDataType::I32,
+        ndim: 2,
+        elem_shape: vec![],
+    }]);
+    assert_eq!(AotMetadata::load(std::path::Path::new("../assets/no_such_module")).unwrap(), None);
+}
+#[test]
+fn test_parse_aot_metadata_errors() {
+    let err = AotMetadata::from_json(None, Some("{")).err().unwrap();
+    assert_eq!(err.code(), TiError::CorruptedData);
+    let err = AotMetadata::from_json(None, Some(r#"{ "g_run": { "args": [] } }"#)).err().unwrap();
+    assert_eq!(err.binding_error(), Some(&BindingError::InvalidMetadata(
+        "graphs.json is not a list of key-value pairs".to_owned())));
+    let graphs = r#"[{ "key": "g_run", "value": { "args": [{ "key": "arr", "value": { "tag": 1, "dtype_id": 5 } }] } }]"#;
+    let err = AotMetadata::from_json(None, Some(graphs)).err().unwrap();
+    assert_eq!(err.binding_error(), Some(&BindingError::InvalidMetadata(
+        "graph argument \"arr\" has no \"field_dim\"".to_owned())));
+    let err = AotMetadata::from_json(Some(r#"{ "kernels": {} }"#), None).err().unwrap();
+    assert_eq!(err.code(), TiError::CorruptedData);
+
+    let module_dir = std::env::temp_dir().join("taichi-test-broken-metadata");
+    let _ = std::fs::remove_dir_all(&module_dir);
+    std::fs::create_dir_all(&module_dir).unwrap();
+    std::fs::write(module_dir.join("graphs.json"), "{").unwrap();
+    let err = AotMetadata::load(&module_dir).err().unwrap();
+    assert_eq!(err.code(), TiError::CorruptedData);
+}
+#[test]
+fn test_aot_module_introspection() {
+    let runtime = Runtime::new(get_platform_arch()).unwrap();
+    let module = runtime.load_aot_module("../assets/chess_board/module").unwrap();
+    assert!(module.metadata().unwrap().is_some());
+    assert!(module.graph_names().unwrap().contains(&"g_run"));
+    let args = module.graph_args("g_run").unwrap();
+    assert_eq!(args.len(), 1);
+    assert_eq!((args[0].name.as_str(), args[0].kind, args[0].dtype, args[0].ndim), ("arr", ArgKind::Ndarray, TiDataType::I32, 2));
+    assert_eq!(module.graph_args("g_rnu").err().unwrap().code(), TiError::NameNotFound);
+
+    assert!(module.get_compute_graph("g_run").unwrap().signature().is_some());
+    let err = module.get_compute_graph("g_rnu").err().unwrap();
+    assert_eq!(err.code(), TiError::NameNotFound);
+    let mut g_run = module.get_compute_graph_unchecked("g_run").unwrap();
+    assert!(g_run.signature().is_none());
+    g_run.set_arg_f32("scale", 1.0).unwrap();
+}
+// Checked without a native module, which couldn't be loaded from a bare
+// `graphs.json` anyway.
+#[test]
+fn test_compute_graph_argument_validation() {
+    use crate::compute_graph::{check_arg, check_args_set};
+    let graphs = r#"[{
+        "key": "g_run",
+        "value": {
+            "args": [
+                { "key": "arr", "value": { "name": "arr", "tag": 1, "dtype_id": 5, "field_dim": 2, "element_shape": [] } },
+                { "key": "scale", "value": { "name": "scale", "tag": 0, "dtype_id": 1, "field_dim": 0, "element_shape": [] } },
+                { "key": "mat", "value": { "name": "mat", "tag": 2, "dtype_id": 1, "field_dim": 0, "element_shape": [2, 2] } },
+                { "key": "mat1", "value": { "name": "mat1", "tag": 2, "dtype_id": 1, "field_dim": 0, "element_shape": [1, 1] } }
+            ]
+        }
+    }]"#;
+    let metadata = AotMetadata::from_json(None, Some(graphs)).unwrap();
+    let g_run = metadata.graph("g_run").unwrap();
+    let ndarray = |elem_type: TiDataType, shape: &[u32], elem_shape: &[u32]| {
+        let mut ndarray = TiNdArray {
+            memory: TiMemory::null(),
+            shape: TiNdShape { dim_count: shape.len() as u32, dims: [0; 16] },
+            elem_shape: TiNdShape { dim_count: elem_shape.len() as u32, dims: [0; 16] },
+            elem_type,
+        };
+        ndarray.shape.dims[..shape.len()].copy_from_slice(shape);
+        ndarray.elem_shape.dims[..elem_shape.len()].copy_from_slice(elem_shape);
+        TiArgument { r#type: TiArgumentType::Ndarray, value: TiArgumentValue { ndarray } }
+    };
+    let arr = ndarray(TiDataType::I32, &[16, 16], &[]);
+    let arr_1d = ndarray(TiDataType::I32, &[16], &[]);
+    let arr_f32 = ndarray(TiDataType::F32, &[16, 16], &[]);
+    let arr_vec = ndarray(TiDataType::I32, &[16, 16], &[2]);
+    let i32_arg = TiArgument { r#type: TiArgumentType::I32, value: TiArgumentValue { r#i32: 1 } };
+    let f32_arg = TiArgument { r#type: TiArgumentType::F32, value: TiArgumentValue { r#f32: 1.0 } };
+
+    let desc = g_run.arg("arr").unwrap();
+    check_arg(desc, &arr).unwrap();
+    let err = check_arg(desc, &arr_1d).err().unwrap();
+    assert_eq!(err.to_string(), "argument \"arr\" ndim mismatched: expected 2, got 1");
+    let err = check_arg(desc, &arr_f32).err().unwrap();
+    assert_eq!(err.to_string(), "argument \"arr\" data type mismatched: expected I32, got F32");
+    let err = check_arg(desc, &arr_vec).err().unwrap();
+    assert_eq!(err.code(), TiError::InvalidArgument);
+
+    let desc = g_run.arg("scale").unwrap();
+    check_arg(desc, &f32_arg).unwrap();
+    let err = check_arg(desc, &i32_arg).err().unwrap();
+    assert_eq!(err.to_string(), "argument \"scale\" data type mismatched: expected F32, got I32");
+    let err = check_arg(desc, &arr_f32).err().unwrap();
+    assert_eq!(err.to_string(), "argument \"scale\" kind mismatched: expected Scalar, got Ndarray");
+
+    let desc = g_run.arg("mat").unwrap();
+    assert_eq!(desc.kind, ArgKind::Matrix);
+    let err = check_arg(desc, &f32_arg).err().unwrap();
+    assert_eq!(err.to_string(), "argument \"mat\" element shape mismatched: expected [2, 2], got []");
+    let desc = g_run.arg("mat1").unwrap();
+    check_arg(desc, &f32_arg).unwrap();
+    let err = check_arg(desc, &i32_arg).err().unwrap();
+    assert_eq!(err.to_string(), "argument \"mat1\" data type mismatched: expected F32, got I32");
+    let err = check_arg(desc, &arr_f32).err().unwrap();
+    assert_eq!(err.to_string(), "argument \"mat1\" kind mismatched: expected Matrix, got Ndarray");
+
+    let mut args = std::collections::HashMap::new();
+    args.insert(std::ffi::CString::new("scale").unwrap(), f32_arg);
+    args.insert(std::ffi::CString::new("mat").unwrap(), f32_arg);
+    args.insert(std::ffi::CString::new("mat1").unwrap(), f32_arg);
+    let err = check_args_set(g_run, &args).err().unwrap();
+    assert_eq!(err.binding_error(), Some(&BindingError::ArgumentNotSet("\"arr\"".to_owned())));
+    args.insert(std::ffi::CString::new("arr").unwrap(), arr);
+    check_args_set(g_run, &args).unwrap();
+}
+#[test]
+fn test_aot_module_sources() {
+    #[cfg(unix)]
+    {
+        use std::os::unix::ffi::OsStrExt;
+        let path = std::path::Path::new(std::ffi::OsStr::from_bytes(b"module\xff"));
+        let path = crate::aot_module::path_to_cstring(path).unwrap();
+        assert_eq!(path.as_bytes(), b"module\xff");
+    }
+    let err = crate::aot_module::path_to_cstring(std::path::Path::new("mod\0ule")).err().unwrap();
+    assert_eq!(err.code(), TiError::InvalidArgument);
+
+    let tcm: &'static [u8] = include_tcm!("mod.rs");
+    assert!(std::str::from_utf8(tcm).unwrap().contains("fn test_aot_module_sources"));
+
+    struct FailingReader;
+    impl std::io::Read for FailingReader {
+        fn read(&mut self, _: &mut [u8]) -> std::io::Result<usize> {
+            Err(std::io::ErrorKind::BrokenPipe.into())
+        }
+    }
+    let runtime = Runtime::new(get_platform_arch()).unwrap();
+    let err = AotModule::from_reader(&runtime, FailingReader).err().unwrap();
+    assert!(matches!(err.binding_error(), Some(BindingError::Io(_))));
+}
+#[test]
+fn test_tcm_pack_and_read() {
+    let module_dir = std::env::temp_dir().join("taichi-test-tcm-module");
+    let _ = std::fs::remove_dir_all(&module_dir);
+    std::fs::create_dir_all(module_dir.join("shaders")).unwrap();
+    std::fs::write(module_dir.join("metadata.json"), r#"{ "kernels": [{ "name": "chess_board" }] }"#).unwrap();
+    std::fs::write(module_dir.join("graphs.json"), r#"[{ "key": "g_run", "value": { "args": [] } }]"#).unwrap();
+    std::fs::write(module_dir.join("shaders/chess_board_c0_t00.spv"), [3, 2, 35, 7]).unwrap();
+    std::fs::write(module_dir.join(".gitignore"), "*").unwrap();
+
+    let tcm = tcm::Tcm::from_dir(&module_dir).unwrap();
+    assert_eq!(tcm.entry_names(), ["graphs.json", "metadata.json", "shaders/chess_board_c0_t00.spv"]);
+    tcm.validate(TiArch::Vulkan).unwrap();
+    let err = tcm.validate(TiArch::X64).err().unwrap();
+    assert_eq!(err.code(), TiError::CorruptedData);
+
+    let bytes = tcm.to_bytes().unwrap();
+    let tcm2 = tcm::Tcm::from_bytes(&bytes).unwrap();
+    assert_eq!(tcm2, tcm);
+    assert_eq!(tcm2.entry("shaders/chess_board_c0_t00.spv"), Some(&[3u8, 2, 35, 7][..]));
+    let metadata = tcm2.metadata().unwrap().unwrap();
+    assert_eq!(metadata.kernel_names(), ["chess_board"]);
+    assert_eq!(metadata.graph_names(), ["g_run"]);
+    let mut archive = tcm::open_archive(&bytes).unwrap();
+    assert_eq!(tcm::read_metadata(&mut archive).unwrap(), Some(metadata));
+
+    let extract_dir = std::env::temp_dir().join("taichi-test-tcm-extract");
+    let _ = std::fs::remove_dir_all(&extract_dir);
+    tcm2.extract_to(&extract_dir).unwrap();
+    assert_eq!(tcm::Tcm::from_dir(&extract_dir).unwrap(), tcm);
+
+    let mut tcm3 = tcm::Tcm::new();
+    assert!(tcm3.insert("../escape.spv", Vec::new()).is_err());
+    tcm3.insert("shader.spv", Vec::new()).unwrap();
+    assert_eq!(tcm3.validate(TiArch::Vulkan).err().unwrap().code(), TiError::CorruptedData);
+    assert!(tcm::Tcm::from_bytes(b"not a zip").is_err());
+
+    let root = std::env::temp_dir().join("taichi-test-tcm-arch-dirs");
+    let _ = std::fs::remove_dir_all(&root);
+    std::fs::create_dir_all(root.join("vulkan")).unwrap();
+    std::fs::write(root.join("vulkan/metadata.json"), r#"{ "kernels": [] }"#).unwrap();
+    let err = tcm::Tcm::from_dir(&root).err().unwrap();
+    assert!(matches!(err.binding_error(), Some(BindingError::InvalidArchive(_))));
+    tcm::Tcm::from_dir(root.join("vulkan")).unwrap();
+}
+#[test]
+fn test_module_bundle_selection() {
+    let root = std::env::temp_dir().join("taichi-test-module-bundle");
+    let _ = std::fs::remove_dir_all(&root);
+    std::fs::create_dir_all(root.join("vulkan")).unwrap();
+    std::fs::create_dir_all(root.join("shaders")).unwrap();
+    std::fs::write(root.join("metal.tcm"), []).unwrap();
+    let bundle = ModuleBundle::from_arch_dirs(&root).unwrap();
+    assert_eq!(bundle.archs(), [TiArch::Vulkan, TiArch::Metal]);
+    assert!(matches!(bundle.source(TiArch::Metal), Some(ModuleSource::TcmFile(_))));
+
+    let runtime = Runtime::new(get_platform_arch()).unwrap();
+    let mut bundle = ModuleBundle::new();
+    for arch in [TiArch::Vulkan, TiArch::Metal, TiArch::X64] {
+        if arch != runtime.arch() {
+            bundle.add_tcm(arch, include_tcm!("mod.rs"));
+        }
+    }
+    let err = bundle.load(&runtime).err().unwrap();
+    assert_eq!(err.code(), TiError::IncompatibleModule);
+    assert_eq!(err.binding_error(), Some(&BindingError::IncompatibleModule {
+        arch: runtime.arch(),
+        available: bundle.archs(),
+    }));
+
+    bundle.add_dir(runtime.arch(), "../assets/chess_board/module");
+    bundle.load(&runtime).unwrap();
+}
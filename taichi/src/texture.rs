@@ -1,11 +1,12 @@
 use taichi_sys::{TiTexture, TiImageDimension, TiImage, TiImageExtent, TiSampler, TiFormat, TiImageUsageFlags};
 
 use crate::{
-    Result, Runtime, image::ImageBuilder, Image
+    Result, Runtime, image::ImageBuilder, Image, Sampler
 };
 
 pub struct TextureBuilder<'a> {
     image_builder: ImageBuilder<'a>,
+    sampler: Option<Sampler>,
     texture: TiTexture,
 }
 impl<'a> TextureBuilder<'a> {
@@ -25,6 +26,7 @@ impl<'a> TextureBuilder<'a> {
         };
         TextureBuilder {
             image_builder,
+            sampler: None,
             texture,
         }
     }
@@ -72,22 +74,31 @@ impl<'a> TextureBuilder<'a> {
         self
     }
 
+    pub fn sampler(&mut self, sampler: &Sampler) -> &mut Self {
+        self.sampler = Some(sampler.clone());
+        self.texture.sampler = sampler.sampler();
+        self
+    }
+
     pub fn build(&mut self) -> Result<Texture> {
         let image = self.image_builder.build()?;
         self.texture.image = image.image();
-        Texture::new(image, self.texture)
+        Texture::new(image, self.sampler.clone(), self.texture)
     }
 }
 
 pub struct Texture {
     image: Image,
+    sampler: Option<Sampler>,
     texture: TiTexture,
 }
 impl Texture {
-    fn new(image: Image, texture: TiTexture) -> Result<Self> {
+    fn new(image: Image, sampler: Option<Sampler>, texture: TiTexture) -> Result<Self> {
         debug_assert_eq!(image.image(), texture.image);
+        debug_assert_eq!(sampler.as_ref().map_or(TiSampler::null(), Sampler::sampler), texture.sampler);
         Ok(Texture {
             image,
+            sampler,
             texture,
         })
     }
@@ -95,6 +106,9 @@ impl Texture {
     pub fn image(&self) -> &Image {
         &self.image
     }
+    pub fn sampler(&self) -> Option<&Sampler> {
+        self.sampler.as_ref()
+    }
     pub fn dimension(&self) -> TiImageDimension {
         self.texture.dimension
     }